@@ -9,6 +9,7 @@ use rustc_hash::{FxHashSet};
 #[derive(Debug, Clone)]
 pub struct AnalyzedExpr<A: Analysis>{
     nodes: Vec<A::Item>,
+    hole: Option<A::Item>, // the analysis of a `??` HOLE child, see new()
     shared: A,
 }
 
@@ -22,15 +23,24 @@ pub trait Analysis: Sized + Debug + Clone {
 
 impl<A: Analysis> AnalyzedExpr<A> {
     pub fn new(shared: A) -> Self {
-        AnalyzedExpr {
+        let mut analyzed = AnalyzedExpr {
             nodes: vec![],
+            hole: None,
             shared,
-        }
+        };
+        // a HOLE child (eg from parsing `??`) is analyzed like a typed hole we know nothing about
+        let mut set = ExprSet::empty(Order::ChildFirst, false, false);
+        let hole = set.add(Node::Hole(0));
+        analyzed.hole = Some(A::new(set.get(hole), &analyzed));
+        analyzed
     }
     
     /// analyze all ndoes up to the index `idx` (inclusive)
     pub fn analyze_to(&mut self, set: &ExprSet, idx: Idx) {
         assert_eq!(set.order, Order::ChildFirst);
+        if idx == HOLE {
+            return
+        }
         while self.nodes.len() <= idx {
             self.nodes.push(A::new(set.get(self.nodes.len()), self));
         }
@@ -47,12 +57,25 @@ impl<A: Analysis> AnalyzedExpr<A> {
         self.analyze_to(e.set, e.idx);
         &self[e.idx]
     }
+
+    /// update the analysis after ExprSet::compact() using the remapping table it returned. This keeps the
+    /// results for surviving nodes, so it's only valid for analyses whose results don't contain Idxs.
+    pub fn remap(&mut self, remap: &[Option<Idx>]) {
+        let mut i = 0;
+        self.nodes.retain(|_| {
+            i += 1;
+            remap[i-1].is_some()
+        });
+    }
 }
 
 impl<A: Analysis> Index<Idx> for AnalyzedExpr<A> {
     type Output = A::Item;
 
     fn index(&self, idx: Idx) -> &Self::Output {
+        if idx == HOLE {
+            return self.hole.as_ref().unwrap()
+        }
         &self.nodes[idx]
     }
 }
@@ -69,13 +92,14 @@ impl Analysis for ExprCost {
     fn new(e: Expr, analyzed: &AnalyzedExpr<Self>) -> Self::Item {
         match e.node() {
             Node::IVar(_) => analyzed.shared.cost_ivar,
+            Node::Hole(_) => 0,
             Node::Var(_) => analyzed.shared.cost_var,
             Node::Prim(p) => *analyzed.shared.cost_prim.get(p).unwrap_or(&analyzed.shared.cost_prim_default),
             Node::App(f, x) => {
-                analyzed.shared.cost_app + analyzed.nodes[*f] + analyzed.nodes[*x] 
+                analyzed.shared.cost_app + analyzed[*f] + analyzed[*x] 
             }
             Node::Lam(b) => {
-                analyzed.shared.cost_lam + analyzed.nodes[*b]
+                analyzed.shared.cost_lam + analyzed[*b]
             }
         }
     }
@@ -86,13 +110,14 @@ impl Analysis for &ExprCost {
     fn new(e: Expr, analyzed: &AnalyzedExpr<Self>) -> Self::Item {
         match e.node() {
             Node::IVar(_) => analyzed.shared.cost_ivar,
+            Node::Hole(_) => 0,
             Node::Var(_) => analyzed.shared.cost_var,
             Node::Prim(p) => *analyzed.shared.cost_prim.get(p).unwrap_or(&analyzed.shared.cost_prim_default),
             Node::App(f, x) => {
-                analyzed.shared.cost_app + analyzed.nodes[*f] + analyzed.nodes[*x] 
+                analyzed.shared.cost_app + analyzed[*f] + analyzed[*x] 
             }
             Node::Lam(b) => {
-                analyzed.shared.cost_lam + analyzed.nodes[*b]
+                analyzed.shared.cost_lam + analyzed[*b]
             }
         }
     }
@@ -105,14 +130,14 @@ impl Analysis for DepthAnalysis {
     type Item = usize;
     fn new(e: Expr, analyzed: &AnalyzedExpr<Self>) -> Self::Item {
         match e.node() {
-            Node::IVar(_) => 1,
+            Node::IVar(_) | Node::Hole(_) => 1,
             Node::Var(_) => 1,
             Node::Prim(_) => 1,
             Node::App(f, x) => {
-                1 + std::cmp::max(analyzed.nodes[*f], analyzed.nodes[*x])
+                1 + std::cmp::max(analyzed[*f], analyzed[*x])
             }
             Node::Lam(b) => {
-                1 + analyzed.nodes[*b]
+                1 + analyzed[*b]
             }
         }
     }
@@ -125,7 +150,7 @@ impl Analysis for FreeVarAnalysis {
     fn new(e: Expr, analyzed: &AnalyzedExpr<Self>) -> Self::Item {
         let mut free: FxHashSet<i32> = Default::default();
         match e.node() {
-            Node::IVar(_) => {},
+            Node::IVar(_) | Node::Hole(_) => {},
             Node::Var(i) => {
                 free.insert(*i);
             },
@@ -155,7 +180,7 @@ impl Analysis for IVarAnalysis {
             Node::IVar(i) => {
                 free.insert(*i);
             },
-            Node::Var(_) => {},
+            Node::Var(_) | Node::Hole(_) => {},
             Node::Prim(_) => {},
             Node::App(f, x) => {
                 free.extend(analyzed[*f].iter());
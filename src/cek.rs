@@ -0,0 +1,280 @@
+use std::time::Duration;
+
+use crate::*;
+
+/// what to do with a value once it's been computed, in eval_iterative()
+enum Frame<'a, D: Domain> {
+    /// it's a function so now evaluate the argument `Idx` in the given env and origin (resolved to the Expr), unless
+    /// the function takes it lazily
+    Arg(Idx, Env<D>, Origin, Expr<'a>),
+    /// it's an argument so apply this function to it
+    Apply(Val<D>),
+    /// it's the value of this invention so remember it, see Evaluator::eval_prim()
+    Invention(Symbol),
+}
+
+/// the state of the machine in eval_iterative(): either an expression to evaluate (with its origin resolved to the
+/// Expr, so that's only done once per closure or thunk) or a value to hand to the top frame
+enum Control<'a, D: Domain> {
+    Eval(Idx, Env<D>, Origin, Expr<'a>),
+    Return(Val<D>),
+}
+
+impl<'a> Expr<'a> {
+    /// eval() with an explicit stack instead of recursion, see Evaluator::eval_iterative()
+    pub fn eval_iterative<D: Domain>(&self, env: &Env<D>, dsl: &DSL<D>, timelimit: Option<Duration>) -> VResult<D> {
+        let mut evaluator = self.as_eval(dsl, timelimit);
+        evaluator.iterative = true;
+        evaluator.eval_child(self.idx, env)
+    }
+}
+
+impl<'a, D: Domain> Evaluator<'a, D> {
+    /// Evaluate `child` (an Idx into the expression given by `origin`) in `env` like eval_child_in(), but as a CEK style
+    /// machine that keeps its continuation in a Vec rather than on the native stack, so deeply nested programs (like long
+    /// chains of `cons`) can't overflow it. It gives the same values and errors, including passing lazy arguments
+    /// as Thunks and partially applying CurriedFns, and uses exactly as much fuel. Calls to a function value in tail
+    /// position (like a closure's body) don't grow the stack at all, and with a max_depth it's the size of this
+    /// stack that's limited. This is used for every eval_child_in() when `self.iterative` is set, including when a
+    /// DSLFn applies a closure with apply() or forces a Thunk, though each such call out of a primitive and back
+    /// still nests on the native stack since DSLFns are ordinary functions. So calls to primitives may only nest
+    /// `self.max_prim_nesting` deep (DEFAULT_MAX_PRIM_NESTING unless changed) before erroring with a
+    /// VError::ResourceLimit, which is what stops eg deep recursion through `fix` from overflowing the native stack.
+    pub fn eval_iterative(&self, origin: &Origin, child: Idx, env: &Env<D>) -> VResult<D> {
+        let base_depth = self.depth.get();
+        let res = self.run(origin, child, env, base_depth);
+        self.depth.set(base_depth);
+        res
+    }
+
+    fn run(&self, origin: &Origin, child: Idx, env: &Env<D>, base_depth: usize) -> VResult<D> {
+        let mut stack: Vec<Frame<'a, D>> = vec![];
+        let push = |stack: &mut Vec<Frame<'a, D>>, frame: Frame<'a, D>| {
+            if let Some(max_depth) = self.max_depth {
+                if base_depth + stack.len() >= max_depth {
+                    return Err(max_depth_error(max_depth))
+                }
+            }
+            stack.push(frame);
+            Ok(())
+        };
+        if let Some(max_depth) = self.max_depth {
+            if base_depth >= max_depth {
+                return Err(max_depth_error(max_depth))
+            }
+        }
+
+        let mut control = Control::Eval(child, env.clone(), origin.clone(), self.expr_of(origin)?);
+        loop {
+            let val = match control {
+                Control::Return(val) => val,
+                Control::Eval(idx, env, origin, expr) => {
+                    self.check_timeout()?;
+                    self.charge(1)?;
+                    if idx == HOLE {
+                        return Err(hole_error())
+                    }
+                    match expr.get_node(idx) {
+                        Node::Var(i) => env.env.get(*i as usize).ok_or(VError::UnboundVar(*i as usize))?.clone(),
+                        Node::IVar(i) => return Err(VError::NotExecutable(format!("attempting to execute a #{} ivar", i))),
+                        Node::Hole(id) => return Err(VError::NotExecutable(format!("attempting to execute a ?{} hole", id))),
+                        Node::Lam(b) => Val::LamClosure(*b, env, origin),
+                        Node::Prim(p) => match self.eval_prim(p)? {
+                            // inventions are thunks of their body
+                            Val::Thunk(body, body_env, body_origin) => {
+                                push(&mut stack, Frame::Invention(p.clone()))?;
+                                let body_expr = self.expr_of(&body_origin)?;
+                                control = Control::Eval(body, body_env, body_origin, body_expr);
+                                continue
+                            },
+                            v => v,
+                        },
+                        Node::App(f, x) => {
+                            let (f, x) = (*f, *x);
+                            push(&mut stack, Frame::Arg(x, env.clone(), origin.clone(), expr))?;
+                            control = Control::Eval(f, env, origin, expr);
+                            continue
+                        },
+                    }
+                },
+            };
+
+            control = match stack.pop() {
+                None => return Ok(val),
+                Some(Frame::Arg(x, env, origin, expr)) => {
+                    let lazy = match &val {
+                        Val::PrimFun(func) => self.next_arg_lazy(func)?,
+                        _ => false,
+                    };
+                    if lazy {
+                        self.apply_step(val, Val::Thunk(x, env, origin), stack.len(), base_depth)?
+                    } else {
+                        push(&mut stack, Frame::Apply(val))?;
+                        Control::Eval(x, env, origin, expr)
+                    }
+                },
+                Some(Frame::Apply(f)) => self.apply_step(f, val, stack.len(), base_depth)?,
+                Some(Frame::Invention(p)) => {
+                    self.inventions.borrow_mut().insert(p, val.clone());
+                    Control::Return(val)
+                },
+            };
+        }
+    }
+
+    /// apply(), except that a closure's body is handed back to evaluate rather than evaluated right away
+    fn apply_step(&self, f: Val<D>, x: Val<D>, stack_len: usize, base_depth: usize) -> Result<Control<'a, D>, VError> {
+        self.charge(1)?;
+        match f {
+            Val::PrimFun(func) => {
+                let nesting = self.prim_nesting.get();
+                if nesting >= self.max_prim_nesting {
+                    return Err(prim_nesting_error(self.max_prim_nesting))
+                }
+                // anything the primitive evaluates nests inside everything on our stack
+                self.depth.set(base_depth + stack_len + 1);
+                self.prim_nesting.set(nesting + 1);
+                let res = func.apply(x, self);
+                self.prim_nesting.set(nesting);
+                self.depth.set(base_depth);
+                Ok(Control::Return(res?))
+            },
+            Val::LamClosure(body, mut env, origin) => {
+                env.push_front(x);
+                let expr = self.expr_of(&origin)?;
+                Ok(Control::Eval(body, env, origin, expr))
+            },
+            _ => Err(VError::ArityError("Expected function or closure".into())),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use domains::simple::SimpleVal;
+    use domains::prim_lists::ListVal;
+
+    /// evaluate recursively and iteratively with the same budgets, checking they agree on the result and fuel used,
+    /// and return whether it succeeded
+    fn agree<D: Domain>(e: Expr, env: &Env<D>, dsl: &DSL<D>, fuel: u64) -> bool {
+        let mut recursive = e.as_eval(dsl, None);
+        recursive.set_fuel(fuel);
+        let mut iterative = e.as_eval(dsl, None);
+        iterative.set_fuel(fuel);
+        iterative.iterative = true;
+        let res = recursive.eval_child(e.idx, env);
+        assert_eq!(res, iterative.eval_child(e.idx, env), "disagree on {}", e);
+        assert_eq!(recursive.fuel_left(), iterative.fuel_left(), "disagree on fuel for {}", e);
+        assert_eq!(recursive.allocated.get(), iterative.allocated.get());
+        res.is_ok()
+    }
+
+    #[test]
+    fn test_eval_iterative() {
+        let dsl = SimpleVal::new_dsl();
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, false);
+        let list: Val<SimpleVal> = dsl.val_of_prim(&"[1,2,3]".into()).unwrap();
+        let env = Env::from(vec![list.clone(), 5.into()]);
+        for s in ["(+ 1 2)", "(map (+ 1) $0)", "(sum (map (lam (* $0 $1)) $0))", "((lam (lam (+ $0 $1))) 3 4)",
+            "(map (lam (map (lam (+ $0 $1)) $1)) $0)", "(+ 1)", "(lam $0)", "$1", "(+ [] 1)", "(1 2)", "(+ $5 1)", "(+ ?0 1)"] {
+            let e = set.parse_extend(s).unwrap();
+            agree(set.get(e), &env, &dsl, 1000);
+        }
+        for s in ["(+ ?? 1)", "((lam ??) 1)"] {
+            let e = set.parse_extend(s).unwrap();
+            assert_eq!(set.get(e).eval_iterative(&env, &dsl, None), Err(VError::NotExecutable("attempting to execute a ?? hole".into())));
+            assert!(!agree(set.get(e), &env, &dsl, 1000));
+        }
+        let e = set.parse_extend("(sum (map (lam (* $0 $0)) $0))").unwrap();
+        assert_eq_val(&set.get(e).eval_iterative(&env, &dsl, None).unwrap(), 14);
+        for fuel in 0..40 {
+            agree(set.get(e), &env, &dsl, fuel);
+        }
+
+        // inventions, including closures passed between them and the program
+        let mut dsl = SimpleVal::new_dsl();
+        for (name, body) in [("inc", "(lam (+ $0 1))"), ("three", "(+ 1 2)"), ("flipmap", "(lam (lam (map $0 $1)))"), ("inc2", "(lam (inc (inc $0)))")] {
+            let mut set = ExprSet::empty(Order::ChildFirst, false, false);
+            let idx = set.parse_extend(body).unwrap();
+            dsl.add_invention(name, ExprOwned::new(set, idx)).unwrap();
+        }
+        for s in ["(inc2 three)", "(flipmap $0 inc2)", "(flipmap $0 (lam (+ $0 $2)))", "(map inc $0)"] {
+            let e = set.parse_extend(s).unwrap();
+            assert!(agree(set.get(e), &env, &dsl, 1000));
+        }
+
+        // random programs
+        let grammar = Grammar::uniform(&dsl);
+        let mut sampler = Sampler::new(&grammar, 0, SampleConfig { max_depth: 6, max_size: 30, max_steps: 1000 });
+        let mut num_ok = 0;
+        for _ in 0..200 {
+            if let Some(e) = sampler.sample(&"list int -> int -> int".parse().unwrap()) {
+                agree(e.immut(), &env, &dsl, 10000);
+                let idx = set.parse_extend(&format!("({} $0 $1)", e)).unwrap();
+                num_ok += agree(set.get(idx), &env, &dsl, 10000) as usize;
+            }
+        }
+        assert!(num_ok > 100);
+
+        // lazy arguments and recursion through fix
+        let dsl = ListVal::new_dsl();
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, false);
+        let env = Env::from(vec![dsl.val_of_prim(&"[1,2,3,4,5]".into()).unwrap()]);
+        for s in ["(if (empty? $0) $0 (cdr $0))", "(if (empty? $0) (car $0) 7)", "(if (empty? empty) 1 (car empty))",
+            "(fix1 $0 (lam (lam (if (empty? $0) 0 (+ 1 ($1 (cdr $0)))))))",
+            "(fix1 $0 (lam (lam (if (empty? $0) $0 (cons (+ 1 (car $0)) ($1 (cdr $0)))))))",
+            "(fix1 $0 (lam (lam (if (empty? $0) $0 (cons (+ 1 (car $0)) ($1 $0))))))", "(car (cdr 1))"] {
+            let e = set.parse_extend(s).unwrap();
+            agree(set.get(e), &env, &dsl, 100000);
+        }
+    }
+
+    #[test]
+    fn test_eval_iterative_deep() {
+        // far too deep to evaluate recursively
+        let dsl = SimpleVal::new_dsl();
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, false);
+        let n = 100_000;
+        let e = set.parse_extend(&format!("{}0{}", "(+ 1 ".repeat(n), ")".repeat(n))).unwrap();
+        assert_eq_val(&set.get(e).eval_iterative(&Env::empty(), &dsl, None).unwrap(), n as i32);
+        let e = set.parse_extend(&format!("{}$0{}", "((lam (+ $0 1)) ".repeat(n), ")".repeat(n))).unwrap();
+        assert_eq_val(&set.get(e).eval_iterative(&Env::from(vec![1.into()]), &dsl, None).unwrap(), n as i32 + 1);
+
+        // and the depth limit is on the explicit stack
+        let mut evaluator = set.get(e).as_eval(&dsl, None);
+        evaluator.iterative = true;
+        evaluator.set_max_depth(1000);
+        assert_eq!(evaluator.eval_child(e, &Env::from(vec![1.into()])), Err(VError::ResourceLimit("Exceeded max eval depth of 1000".into())));
+        assert_eq!(evaluator.depth.get(), 0);
+    }
+
+    /// `fix` without prim_lists' limit on how many invocations may nest
+    fn deep_fix(mut args: Env<ListVal>, handle: &Evaluator<ListVal>) -> VResult<ListVal> {
+        load_args!(args, fn_val: Val<ListVal>, x: Val<ListVal>);
+        let fixf = handle.apply(PrimFun(CurriedFn::new("deep_fix".into(), 2)), fn_val.clone())?;
+        let ffixf = handle.apply(fn_val, fixf)?;
+        handle.apply(ffixf, x)
+    }
+
+    #[test]
+    fn test_eval_iterative_deep_fix() {
+        let mut dsl = ListVal::new_dsl();
+        dsl.add_entry(Production::func("deep_fix", "((t0 -> t1) -> t0 -> t1) -> t0 -> t1", deep_fix));
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, false);
+        let e = set.parse_extend("(deep_fix (lam (lam (if (eq? $0 0) 0 (+ 1 ($1 (- $0 1)))))) $0)").unwrap();
+        let env = |n: i32| Env::from(vec![Val::from(n)]);
+
+        // each level of recursion nests `deep_fix` and `if` on the native stack, so it's stopped well before that
+        // overflows rather than aborting the process
+        let n = 100_000;
+        assert_eq!(set.get(e).eval_iterative(&env(n), &dsl, None), Err(VError::ResourceLimit(format!("Exceeded max primitive nesting of {}", DEFAULT_MAX_PRIM_NESTING))));
+        let mut evaluator = set.get(e).as_eval(&dsl, None);
+        evaluator.iterative = true;
+        assert!(evaluator.eval_child(e, &env(n)).is_err());
+        assert_eq!((evaluator.prim_nesting.get(), evaluator.depth.get()), (0, 0));
+        // while shallower recursion is fine
+        assert_eq_val(&set.get(e).eval_iterative(&env(40), &dsl, None).unwrap(), 40);
+    }
+}
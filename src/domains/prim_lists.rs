@@ -29,7 +29,7 @@ impl FromVal<ListVal> for i32 {
     fn from_val(v: Val) -> Result<Self, VError> {
         match v {
             Dom(Int(i)) => Ok(i),
-            _ => Err("from_val_to_list: not an int".into())
+            _ => Err(VError::mismatch("from_val_to_list: not an int"))
         }
     }
 }
@@ -37,7 +37,7 @@ impl FromVal<ListVal> for bool {
     fn from_val(v: Val) -> Result<Self, VError> {
         match v {
             Dom(Bool(b)) => Ok(b),
-            _ => Err("from_val_to_bool: not a bool".into())
+            _ => Err(VError::mismatch("from_val_to_bool: not a bool"))
         }
     }
 }
@@ -46,7 +46,7 @@ impl<T: FromVal<ListVal>> FromVal<ListVal> for Vec<T>
     fn from_val(v: Val) -> Result<Self, VError> {
         match v {
             Dom(List(v)) => v.into_iter().map(|v| T::from_val(v)).collect(),
-            _ => Err("from_val_to_vec: not a list".into())
+            _ => Err(VError::mismatch("from_val_to_vec: not a list"))
         }
     }
 }
@@ -164,6 +164,13 @@ impl Domain for ListVal {
         }
     }
 
+    fn size(&self) -> usize {
+        match self {
+            List(xs) => 1 + xs.len(),
+            _ => 1,
+        }
+    }
+
 }
 
 // *********************
@@ -215,7 +222,7 @@ fn is_empty(mut args: Env, _handle: &Evaluator) -> VResult {
 fn car(mut args: Env, _handle: &Evaluator) -> VResult {
     load_args!(args, xs: Vec<Val>);
     if xs.is_empty() {
-        Err(String::from("car called on empty list"))
+        Err("car called on empty list".into())
     } else {
         ok(xs[0].clone())
     }
@@ -224,7 +231,7 @@ fn car(mut args: Env, _handle: &Evaluator) -> VResult {
 fn cdr(mut args: Env, _handle: &Evaluator) -> VResult {
     load_args!(args, xs: Vec<Val>);
     if xs.is_empty() {
-        Err(String::from("cdr called on empty list"))
+        Err("cdr called on empty list".into())
     } else {
         ok(xs[1..].to_vec())
     }
@@ -238,20 +245,21 @@ pub static FIX: Lazy<Val> = Lazy::new(|| PrimFun(CurriedFn::new(Symbol::from("fi
 /// type i think: ((t0 -> t1) -> t0 -> t1) -> t0 -> t1 
 fn fix(mut args: Env, handle: &Evaluator) -> VResult {
     handle.data.borrow_mut().fix_counter += 1;
-    if handle.data.borrow().fix_counter > MAX_FIX_INVOCATIONS {
-        return Err(format!("Exceeded max number of fix invocations. Max was {}", MAX_FIX_INVOCATIONS));
-    }
-    load_args!(args, fn_val: Val, x: Val);
-
-    // fix f x = f(fix f)(x)
-    let fixf = handle.apply(FIX.clone(), fn_val.clone()).unwrap();
-    let res = match handle.apply(fn_val, fixf) {
-        Ok(ffixf) => handle.apply(ffixf, x),
-        Err(err) => Err(format!("Could not apply fixf to f: {}",err))
-    };
+    // the counter goes back down however this returns
+    let res = (|| {
+        if handle.data.borrow().fix_counter > MAX_FIX_INVOCATIONS {
+            return Err(VError::ResourceLimit(format!("Exceeded max number of fix invocations. Max was {}", MAX_FIX_INVOCATIONS)));
+        }
+        load_args!(args, fn_val: Val, x: Val);
+
+        // fix f x = f(fix f)(x)
+        let fixf = handle.apply(FIX.clone(), fn_val.clone())?;
+        // errors are passed on as is so that eg a timeout deep in the recursion is still a timeout
+        let ffixf = handle.apply(fn_val, fixf)?;
+        handle.apply(ffixf, x)
+    })();
     handle.data.borrow_mut().fix_counter -= 1;
     res
-    // handle.apply(fn_val, fixf)
 }
 
 
@@ -338,4 +346,84 @@ mod tests {
             &[arg],
             format!("Exceeded max number of fix invocations. Max was {}", MAX_FIX_INVOCATIONS));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_errors_prim_lists() {
+        let dsl = ListVal::new_dsl();
+        let eval = |s: &str, args: Vec<Val>, timelimit: Option<std::time::Duration>| {
+            let mut set = ExprSet::empty(Order::ChildFirst, false, false);
+            let e = set.parse_extend(s).unwrap();
+            set.get(e).eval(&Env::from(args), &dsl, timelimit).unwrap_err()
+        };
+        let empty = dsl.val_of_prim(&"empty".into()).unwrap();
+        let list = dsl.val_of_prim(&"[1,2,3]".into()).unwrap();
+
+        assert_eq!(eval("(car $0)", vec![empty], None), VError::Domain("car called on empty list".into()));
+        let err = eval("(+ 1 (car $0))", vec![list.clone()], Some(std::time::Duration::ZERO));
+        assert_eq!(err, VError::Timeout);
+        assert!(err.is_timeout());
+        assert_eq!(err.to_string(), "Eval Timeout");
+
+        // mismatches know which production and argument they're from
+        let err = eval("(+ 1 $0)", vec![list.clone()], None);
+        assert_eq!(err, VError::TypeMismatch { prim: Some("+".into()), arg: Some(1), msg: "from_val_to_list: not an int".into() });
+        assert_eq!(err.to_string(), "type mismatch in argument 1 of `+`: from_val_to_list: not an int");
+        assert!(matches!(eval("(car (cdr 1))", vec![], None), VError::TypeMismatch { prim: Some(p), arg: Some(0), .. } if &*p == "cdr"));
+
+        assert_eq!(eval("(1 1)", vec![], None), VError::ArityError("Expected function or closure".into()));
+        let err = eval("(fix1 $0 (lam (lam ($1 $0))))", vec![list], None);
+        assert!(matches!(err, VError::ResourceLimit(_)));
+        // the limit isn't hidden behind other errors on the way out of the recursion
+        assert_eq!(err.to_string(), format!("Exceeded max number of fix invocations. Max was {}", MAX_FIX_INVOCATIONS));
+    }
+
+    #[test]
+    fn test_limits_prim_lists() {
+        let dsl = ListVal::new_dsl();
+        let arg = dsl.val_of_prim(&"[1,2,3,4,5]".into()).unwrap();
+        let mut set = ExprSet::empty(Order::ChildFirst, false, false);
+        let e = set.parse_extend("(fix1 $0 (lam (lam (if (empty? $0) $0 (cons (+ 1 (car $0)) ($1 (cdr $0)))))))").unwrap();
+        let env = Env::from(vec![arg]);
+
+        let evaluator = set.get(e).as_eval(&dsl, None);
+        assert_eq_val(&evaluator.eval_child(e, &env).unwrap(), vec![2, 3, 4, 5, 6]);
+        assert_eq!(evaluator.depth.get(), 0);
+        let allocated = evaluator.allocated.get();
+        assert!(allocated > 5);
+        // the smallest depth limit it runs under
+        let max_depth = (1..1000).find(|&d| {
+            let mut evaluator = set.get(e).as_eval(&dsl, None);
+            evaluator.set_max_depth(d);
+            evaluator.eval_child(e, &env).is_ok()
+        }).unwrap();
+
+        // recursion deeper than the limit is an error long before fix's own limit
+        let mut evaluator = set.get(e).as_eval(&dsl, None);
+        evaluator.set_max_depth(max_depth - 1);
+        assert_eq!(evaluator.eval_child(e, &env), Err(VError::ResourceLimit(format!("Exceeded max eval depth of {}", max_depth - 1))));
+        assert_eq!(evaluator.depth.get(), 0);
+        assert_eq!(evaluator.data.borrow().fix_counter, 0);
+
+        // as is allocating too much
+        let mut evaluator = set.get(e).as_eval(&dsl, None);
+        evaluator.set_max_allocated(allocated);
+        assert!(evaluator.eval_child(e, &env).is_ok());
+        let mut evaluator = set.get(e).as_eval(&dsl, None);
+        evaluator.set_max_allocated(allocated - 1);
+        assert_eq!(evaluator.eval_child(e, &env), Err(VError::ResourceLimit(format!("Exceeded max allocated elements of {}", allocated - 1))));
+
+        // lists that grow with every level of recursion
+        let e = set.parse_extend("(fix1 $0 (lam (lam ($1 (cons 1 $0)))))").unwrap();
+        let mut evaluator = set.get(e).as_eval(&dsl, None);
+        evaluator.set_max_allocated(50);
+        assert_eq!(evaluator.eval_child(e, &Env::from(vec![dsl.val_of_prim(&"[]".into()).unwrap()])),
+            Err(VError::ResourceLimit("Exceeded max allocated elements of 50".into())));
+        assert_eq!(evaluator.data.borrow().fix_counter, 0);
+
+        // and running into fix's own limit leaves the count where it started too
+        let e = set.parse_extend("(fix1 $0 (lam (lam ($1 $0))))").unwrap();
+        let evaluator = set.get(e).as_eval(&dsl, None);
+        assert!(matches!(evaluator.eval_child(e, &env), Err(VError::ResourceLimit(_))));
+        assert_eq!(evaluator.data.borrow().fix_counter, 0);
+    }
+}
@@ -1,4 +1,4 @@
-/// This is an example domain, heavily commented to explain how to implement your own!
+//! This is an example domain, heavily commented to explain how to implement your own!
 
 use crate::*;
 
@@ -37,7 +37,7 @@ impl FromVal<SimpleVal> for i32 {
     fn from_val(v: Val) -> Result<Self, VError> {
         match v {
             Dom(Int(i)) => Ok(i),
-            _ => Err("from_val_to_i32: not an int".into())
+            _ => Err(VError::mismatch("from_val_to_i32: not an int"))
         }
     }
 }
@@ -45,7 +45,7 @@ impl<T: FromVal<SimpleVal>> FromVal<SimpleVal> for Vec<T> {
     fn from_val(v: Val) -> Result<Self, VError> {
         match v {
             Dom(List(v)) => v.into_iter().map(|v| T::from_val(v)).collect(),
-            _ => Err("from_val_to_vec: not a list".into())
+            _ => Err(VError::mismatch("from_val_to_vec: not a list"))
         }
     }
 }
@@ -118,6 +118,13 @@ impl Domain for SimpleVal {
         }
     }
 
+    fn size(&self) -> usize {
+        match self {
+            List(xs) => 1 + xs.len(),
+            _ => 1,
+        }
+    }
+
 
 }
 
@@ -156,8 +163,11 @@ fn map(mut args: Env, handle: &Evaluator) -> VResult {
         .collect::<Result<Vec<Val>,_>>()?)
 }
 
-fn sum(mut args: Env, _handle: &Evaluator) -> VResult {
+fn sum(mut args: Env, handle: &Evaluator) -> VResult {
     load_args!(args, xs: Vec<i32>);
+    // if the evaluator has a fuel limit, handle.charge() lets a primitive use up fuel in proportion to the work
+    // it does (calls to handle.apply() are already charged for). Without a limit it does nothing.
+    handle.charge(xs.len() as u64)?;
     ok(xs.iter().sum::<i32>())
 }
 
@@ -233,4 +243,173 @@ mod tests {
         assert_execution("(map (lam (* $0 $0)) (map (lam (+ (sum $1) $0)) $0))", &[arg], vec![49,64,81]);
 
     }
+
+    #[test]
+    fn test_inventions_simple() {
+        fn owned(s: &str) -> ExprOwned {
+            let mut set = ExprSet::empty(Order::ChildFirst, false, false);
+            let idx = set.parse_extend(s).unwrap();
+            ExprOwned::new(set, idx)
+        }
+        fn eval(dsl: &DSL<SimpleVal>, s: &str, args: &[Val]) -> Val {
+            let e = owned(s);
+            e.immut().eval(&Env::from(args), dsl, None).unwrap()
+        }
+
+        let mut dsl = SimpleVal::new_dsl();
+        dsl.add_invention("inc", owned("(lam (+ $0 1))")).unwrap();
+        dsl.add_invention("three", owned("(+ 1 2)")).unwrap();
+        dsl.add_invention("mapsq", owned("(map (lam (* $0 $0)))")).unwrap();
+        dsl.add_invention("flipmap", owned("(lam (lam (map $0 $1)))")).unwrap();
+        dsl.add_invention("inc2", owned("(lam (inc (inc $0)))")).unwrap();
+
+        // types are inferred from the bodies
+        assert_eq!(dsl.type_of_prim(&"inc".into()), "int -> int".parse::<SlowType>().unwrap());
+        assert_eq!(dsl.type_of_prim(&"three".into()), "int".parse::<SlowType>().unwrap());
+        assert_eq!(dsl.productions.get(&Symbol::from("flipmap")).unwrap().arity, 2);
+        assert_eq!(owned("(map inc2 [1,2])").immut().infer::<SimpleVal>(&mut Context::empty(), &mut Default::default(), &dsl),
+            Ok("list int".parse::<SlowType>().unwrap()));
+        assert!(matches!(dsl.add_invention("bad", owned("(+ [] 1)")), Err(InventionError::Type(_))));
+        // bodies that can't be typed or run at all
+        assert_eq!(dsl.add_invention("bad", owned("(lam (+ $0 $1))")), Err(InventionError::FreeVar(0)));
+        assert_eq!(dsl.add_invention("bad", owned("(+ nope 1)")), Err(InventionError::UnknownPrimitive("nope".into())));
+        assert_eq!(dsl.add_invention("bad", owned("(+ #0 1)")), Err(InventionError::IVar(0)));
+        assert_eq!(dsl.add_invention("bad", owned("(+ ?3 1)")), Err(InventionError::Hole(Some(3))));
+        assert_eq!(dsl.add_invention("bad", owned("(+ ?? 1)")), Err(InventionError::Hole(None)));
+        assert!(!dsl.productions.contains_key(&Symbol::from("bad")));
+
+        assert_eq_val(&eval(&dsl, "(inc 2)", &[]), 3);
+        assert_eq_val(&eval(&dsl, "(+ three three)", &[]), 6);
+        assert_eq_val(&eval(&dsl, "(inc2 $0)", &[Dom(Int(5))]), 7);
+        // closures from an invention body passed to the program and vice versa
+        assert_eq_val(&eval(&dsl, "(map inc [1,2,3])", &[]), vec![2,3,4]);
+        assert_eq_val(&eval(&dsl, "(mapsq [1,2,3])", &[]), vec![1,4,9]);
+        assert_eq_val(&eval(&dsl, "(flipmap [1,2,3] (lam (+ $0 $1)))", &[Dom(Int(10))]), vec![11,12,13]);
+        assert_eq_val(&eval(&dsl, "(flipmap [1,2,3] inc2)", &[]), vec![3,4,5]);
+
+        // an invention's body is only evaluated the first time it's referenced
+        let fuel_used = |s: &str| {
+            let e = owned(s);
+            let mut evaluator = e.immut().as_eval(&dsl, None);
+            evaluator.set_fuel(1000);
+            evaluator.eval_child(e.idx, &Env::empty()).unwrap();
+            assert!(evaluator.inventions.borrow().contains_key(&Symbol::from("three")));
+            1000 - evaluator.fuel_left().unwrap()
+        };
+        assert_eq!(fuel_used("(+ three three)"), fuel_used("(+ three 1)"));
+    }
+
+    #[test]
+    fn test_eval_errors_simple() {
+        let mut dsl = SimpleVal::new_dsl();
+        let mut set = ExprSet::empty(Order::ChildFirst, false, false);
+        let inc = set.parse_extend("(lam (+ $0 1))").unwrap();
+        dsl.add_invention("inc", ExprOwned::new(set, inc)).unwrap();
+        let eval = |s: &str, args: Vec<Val>| {
+            let mut set = ExprSet::empty(Order::ChildFirst, false, false);
+            let e = set.parse_extend(s).unwrap();
+            set.get(e).eval(&Env::from(args), &dsl, None).unwrap_err()
+        };
+
+        assert_eq!(eval("(+ #0 1)", vec![]), VError::NotExecutable("attempting to execute a #0 ivar".into()));
+        assert_eq!(eval("(+ 1 ?2)", vec![]), VError::NotExecutable("attempting to execute a ?2 hole".into()));
+        assert_eq!(eval("(+ ?? 1)", vec![]), VError::NotExecutable("attempting to execute a ?? hole".into()));
+        assert_eq!(eval("((lam ??) 1)", vec![]), VError::NotExecutable("attempting to execute a ?? hole".into()));
+        assert_eq!(eval("(+ foo 1)", vec![]), VError::UnknownPrimitive("foo".into()));
+        assert_eq!(eval("(+ $1 1)", vec![Dom(Int(1))]), VError::UnboundVar(1));
+        assert_eq!(eval("((lam $1) 1)", vec![]), VError::UnboundVar(1));
+
+        // functions that reference productions that don't exist or can't be called
+        let missing = PrimFun(CurriedFn::new("nope".into(), 1));
+        assert_eq!(eval("($0 1)", vec![missing]), VError::UnknownPrimitive("nope".into()));
+        assert_eq!(eval("(map $0 [1])", vec![PrimFun(CurriedFn::new("nope".into(), 1))]), VError::UnknownPrimitive("nope".into()));
+        let no_fn_ptr = PrimFun(CurriedFn::new("inc".into(), 1));
+        assert!(matches!(eval("($0 1)", vec![no_fn_ptr]), VError::NotExecutable(_)));
+        let not_invention = LamClosure(0, Env::empty(), Some("+".into()));
+        assert_eq!(eval("($0 1)", vec![not_invention]), VError::NotExecutable("`+` is not an invention".into()));
+        let unknown_origin = LamClosure(0, Env::empty(), Some("nope".into()));
+        assert_eq!(eval("($0 1)", vec![unknown_origin]), VError::UnknownPrimitive("nope".into()));
+
+        // and none of it gets in the way of programs that are fine
+        let mut set = ExprSet::empty(Order::ChildFirst, false, false);
+        let e = set.parse_extend("(inc $0)").unwrap();
+        assert_eq_val(&set.get(e).eval(&Env::from(vec![Dom(Int(1))]), &dsl, None).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_fuel_simple() {
+        let dsl = SimpleVal::new_dsl();
+        let mut set = ExprSet::empty(Order::ChildFirst, false, false);
+        let run = |set: &mut ExprSet, s: &str, fuel: Option<u64>, timelimit: Option<std::time::Duration>| {
+            let e = set.parse_extend(s).unwrap();
+            let mut evaluator = set.get(e).as_eval(&dsl, timelimit);
+            if let Some(fuel) = fuel {
+                evaluator.set_fuel(fuel);
+            }
+            let res = evaluator.eval_child(e, &Env::empty());
+            (res, evaluator.fuel_left())
+        };
+
+        // (+ 1 2) evaluates 5 nodes, applies + twice, and calls it once
+        assert_eq!(run(&mut set, "(+ 1 2)", None, None).1, None);
+        let (res, left) = run(&mut set, "(+ 1 2)", Some(100), None);
+        assert_eq_val(&res.unwrap(), 3);
+        assert_eq!(left, Some(92));
+        assert_eq_val(&run(&mut set, "(+ 1 2)", Some(8), None).0.unwrap(), 3);
+        assert_eq!(run(&mut set, "(+ 1 2)", Some(7), None).0, Err(VError::OutOfFuel));
+
+        // the same every time, and primitives can charge extra
+        let s = "(sum (map (lam (* $0 $0)) [1,2,3,4,5,6,7,8,9,10]))";
+        let (res, left) = run(&mut set, s, Some(1000), None);
+        assert_eq_val(&res.unwrap(), 385);
+        let used = 1000 - left.unwrap();
+        for _ in 0..3 {
+            assert_eq!(run(&mut set, s, Some(1000), None).1, Some(1000 - used));
+        }
+        assert!(run(&mut set, s, Some(used), None).0.is_ok());
+        assert_eq!(run(&mut set, s, Some(used - 1), None).0, Err(VError::OutOfFuel));
+        assert!(used > run(&mut set, "(map (lam (* $0 $0)) [1,2,3,4,5,6,7,8,9,10])", Some(1000), None).1.map(|left| 1000 - left).unwrap() + 10);
+
+        // both budgets at once
+        assert_eq!(run(&mut set, s, Some(used - 1), Some(std::time::Duration::from_secs(60))).0, Err(VError::OutOfFuel));
+        assert_eq!(run(&mut set, s, Some(1000), Some(std::time::Duration::ZERO)).0, Err(VError::Timeout));
+    }
+
+    #[test]
+    fn test_candidates_simple() {
+        let dsl = SimpleVal::new_dsl();
+        let tp = |s: &str| s.parse::<SlowType>().unwrap();
+        fn summary(candidates: &[Candidate]) -> Vec<String> {
+            candidates.iter().map(|c| format!("{} {}", c.node, c.arg_tps.iter().map(|tp| tp.to_string()).collect::<Vec<_>>().join(","))).collect()
+        }
+
+        let mut ctx = Context::empty();
+        let env: std::collections::VecDeque<SlowType> = vec![tp("list int"), tp("int -> int")].into();
+        let candidates = dsl.candidates(&tp("int"), &env, &mut ctx);
+        assert_eq!(summary(&candidates), vec!["* int,int", "+ int,int", "0 ", "1 ", "2 ", "sum (list int)", "$1 int"]);
+        // ctx is left as it was
+        assert_eq!(ctx, Context::empty());
+
+        // partial application and polymorphism
+        let candidates = dsl.candidates(&tp("int -> int"), &env, &mut ctx);
+        assert_eq!(summary(&candidates), vec!["* int", "+ int", "$1 "]);
+        let candidates = dsl.candidates(&tp("list int"), &Default::default(), &mut ctx);
+        assert_eq!(summary(&candidates), vec!["[] ", "map (t0 -> int),(list t0)"]);
+        // the type variables in the argument types are bound in each candidate's context
+        let map = &candidates[1];
+        let mut map_ctx = map.ctx.clone();
+        assert!(map_ctx.unify(&map.arg_tps[1], &tp("list (list int)")).is_ok());
+        assert_eq!(map.arg_tps[0].apply(&map_ctx), tp("list int -> int"));
+
+        // type variables in the hole type and environment come from the caller's context
+        let mut ctx = Context::empty();
+        let hole_tp = tp("t0").instantiate(&mut ctx);
+        let var_tp = tp("t0").instantiate(&mut ctx);
+        ctx.unify(&var_tp, &tp("list int -> int")).unwrap();
+        let candidates = dsl.candidates(&hole_tp, &vec![var_tp].into(), &mut ctx);
+        assert!(summary(&candidates).contains(&"$0 (list int)".to_string()));
+        assert!(summary(&candidates).contains(&"$0 ".to_string()));
+        let sum = candidates.iter().find(|c| c.node == Node::Prim("sum".into()) && c.arg_tps.len() == 1).unwrap();
+        assert_eq!(hole_tp.apply(&sum.ctx), tp("int"));
+    }
 }
\ No newline at end of file
@@ -1,6 +1,6 @@
 use crate::*;
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::{Debug};
 use std::hash::Hash;
 
@@ -15,15 +15,46 @@ pub struct Production<D: Domain> {
     pub arity: usize,
     pub lazy_args: HashSet<usize>,
     pub fn_ptr: Option<DSLFn<D>>,
+    pub body: Option<ExprOwned>, // for inventions, the closed lambda calculus expression defining the semantics
 }
 
 impl<D:Domain> Debug for Production<D> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Production").field("name", &self.name).field("val", &self.val).field("tp", &self.tp).field("arity", &self.arity).finish()
+        f.debug_struct("Production").field("name", &self.name).field("val", &self.val).field("tp", &self.tp).field("arity", &self.arity).field("body", &self.body.as_ref().map(|body| body.to_string())).finish()
     }
 }
 
 
+/// Why an expression can't be the body of an invention, see Production::invention()
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InventionError {
+    /// the body has a free variable, which would be `$i` just outside the body
+    FreeVar(i32),
+    /// the body has the IVar `#i`
+    IVar(i32),
+    /// the body has a typed hole, or a `??` child for None
+    Hole(Option<HoleId>),
+    /// the body uses a primitive that isn't in the DSL
+    UnknownPrimitive(Symbol),
+    /// the body doesn't typecheck
+    Type(UnifyErr),
+}
+
+impl From<UnifyErr> for InventionError {
+    fn from(err: UnifyErr) -> Self {
+        InventionError::Type(err)
+    }
+}
+
+/// A way to fill a hole of some requested type: a primitive or variable applied to
+/// `arg_tps.len()` arguments, see DSL::candidates()
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    pub node: Node, // Node::Prim or Node::Var
+    pub arg_tps: Vec<SlowType>, // the types of each argument it must be applied to, already apply()ed to `ctx`
+    pub ctx: Context, // the context after unifying its return type with the requested type
+}
+
 #[derive(Clone, Debug)]
 pub struct DSL<D:Domain> {
     pub productions: HashMap<Symbol,Production<D>>,
@@ -37,7 +68,7 @@ impl<D: Domain> Production<D> {
     }
 
     pub fn func(name: &str, tp: &str, fn_ptr: DSLFn<D>) -> Self {
-        Production::func_custom(name.into(), tp, Default::default(), fn_ptr)
+        Production::func_custom(name, tp, Default::default(), fn_ptr)
     }
 
     pub fn func_custom(name: &str, tp: &str, lazy_args: Option<&[usize]>, fn_ptr: DSLFn<D>) -> Self {
@@ -54,6 +85,7 @@ impl<D: Domain> Production<D> {
             arity: 0,
             lazy_args: Default::default(),
             fn_ptr: None,
+            body: None,
         }
     }
 
@@ -66,9 +98,49 @@ impl<D: Domain> Production<D> {
             arity,
             lazy_args,
             fn_ptr: Some(fn_ptr),
+            body: None,
         }
     }
 
+    /// an invention (eg a learned library function) whose semantics are given by the closed
+    /// expression `body` rather than a DSLFn. Its type is inferred from the body using `dsl`, so the
+    /// body may refer to any primitives (including other inventions) already in `dsl`. Errors if the body
+    /// isn't closed, contains IVars or holes, uses unknown primitives, or doesn't typecheck.
+    pub fn invention(name: &str, body: ExprOwned, dsl: &DSL<D>) -> Result<Self, InventionError> {
+        // check what infer() can't handle first, walking with the number of enclosing lambdas
+        let mut worklist = vec![(body.idx, 0)];
+        while let Some((i, depth)) = worklist.pop() {
+            if i == HOLE {
+                return Err(InventionError::Hole(None))
+            }
+            match &body.set[i] {
+                Node::Var(v) if *v >= depth => return Err(InventionError::FreeVar(*v - depth)),
+                Node::IVar(v) => return Err(InventionError::IVar(*v)),
+                Node::Hole(id) => return Err(InventionError::Hole(Some(*id))),
+                Node::Prim(p) if dsl.val_of_prim(p).is_none() => return Err(InventionError::UnknownPrimitive(p.clone())),
+                Node::Var(_) | Node::Prim(_) => {},
+                Node::App(f, x) => worklist.extend([(*f, depth), (*x, depth)]),
+                Node::Lam(b) => worklist.push((*b, depth + 1)),
+            }
+        }
+        let mut ctx = Context::empty();
+        let tp = body.immut().infer::<D>(&mut ctx, &mut VecDeque::new(), dsl)?;
+        Ok(Production::invention_raw(name.into(), tp.apply(&ctx), body))
+    }
+
+    pub fn invention_raw(name: Symbol, tp: SlowType, body: ExprOwned) -> Self {
+        let arity = tp.arity();
+        Production {
+            name: name.clone(),
+            // evaluating the invention means evaluating its body
+            val: Val::Thunk(body.idx, Env::empty(), Some(name)),
+            tp,
+            arity,
+            lazy_args: Default::default(),
+            fn_ptr: None,
+            body: Some(body),
+        }
+    }
 
 
 }
@@ -85,8 +157,15 @@ impl<D: Domain> DSL<D> {
         self.productions.insert(entry.name.clone(), entry);
     }
 
+    /// infer the type of `body` and add it to the DSL as an invention
+    pub fn add_invention(&mut self, name: &str, body: ExprOwned) -> Result<(), InventionError> {
+        let entry = Production::invention(name, body, self)?;
+        self.add_entry(entry);
+        Ok(())
+    }
+
     /// given a primitive's symbol return a runtime Val object. For function primitives
-    /// this should return a PrimFun(CurriedFn) object.
+    /// this should return a PrimFun(CurriedFn) object, and for inventions a Thunk of their body.
     pub fn val_of_prim(&self, p: &Symbol) -> Option<Val<D>> {
         self.productions.get(p).map(|entry| entry.val.clone()).or_else(||
             D::val_of_prim_fallback(p))
@@ -98,8 +177,49 @@ impl<D: Domain> DSL<D> {
         })
     }
 
+    /// Everything that can fill a hole of type `tp` in the environment `env` (where `env[i]` is the type of `$i`) given
+    /// the type bindings in `ctx`: each production and variable, applied to every number of arguments (from none up to
+    /// its arity) that leaves a type unifying with `tp`. Productions come first in order of name, then variables
+    /// in order of index, each with the fewest arguments first. Production types are instantiated with fresh type
+    /// variables while variable types are used as is, since they already live in `ctx`. `ctx` is rolled back
+    /// after trying each one so it's unchanged on return.
+    pub fn candidates(&self, tp: &SlowType, env: &VecDeque<SlowType>, ctx: &mut Context) -> Vec<Candidate> {
+        let tp = tp.apply(ctx);
+        let mut candidates = vec![];
+
+        let mut try_fill = |node: Node, node_tp: &SlowType, instantiate: bool, ctx: &mut Context| {
+            for num_args in 0..=node_tp.arity() {
+                // quick check before any allocation
+                if !Context::might_unify(drop_args(node_tp, num_args), &tp) {
+                    continue
+                }
+                let state = ctx.save_state();
+                let node_tp = if instantiate { node_tp.instantiate(ctx) } else { node_tp.apply(ctx) };
+                if ctx.unify(drop_args(&node_tp, num_args), &tp).is_ok() {
+                    let arg_tps = node_tp.iter_args().take(num_args).map(|arg_tp| arg_tp.apply(ctx)).collect();
+                    candidates.push(Candidate { node: node.clone(), arg_tps, ctx: ctx.clone() });
+                }
+                ctx.load_state(state);
+            }
+        };
+
+        let mut names: Vec<&Symbol> = self.productions.keys().collect();
+        names.sort();
+        for name in names {
+            try_fill(Node::Prim(name.clone()), &self.productions[name].tp, true, ctx);
+        }
+        for (i, var_tp) in env.iter().enumerate() {
+            try_fill(Node::Var(i as i32), &var_tp.apply(ctx), false, ctx);
+        }
+        candidates
+    }
+
 }
 
+/// the type left after applying something of type `tp` to `num_args` arguments
+fn drop_args(tp: &SlowType, num_args: usize) -> &SlowType {
+    (0..num_args).fold(tp, |tp, _| tp.as_arrow().unwrap().1)
+}
 
 /// The key trait that defines a domain
 pub trait Domain: Clone + Debug + PartialEq + Eq + Hash + Send + Sync {
@@ -110,5 +230,12 @@ pub trait Domain: Clone + Debug + PartialEq + Eq + Hash + Send + Sync {
     fn type_of_dom_val(&self) -> SlowType;
 
     fn new_dsl() -> DSL<Self>;
+
+    /// roughly how many elements were allocated to build this value, eg 1 plus the length of a list, for
+    /// Evaluator::set_max_allocated(). Since the elements of a list are cloned out of values that already
+    /// exist, counting just the outer list is enough to catch runaway growth.
+    fn size(&self) -> usize {
+        1
+    }
 }
 
@@ -0,0 +1,543 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashSet, VecDeque};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::*;
+
+/// Enumerate every well typed closed program whose type unifies with `tp`, in increasing order of cost under
+/// `cost_fn` up to and including `max_cost`, returning each program's Idx in `set` along with its cost. Programs are
+/// built bottom up out of smaller well typed (possibly open) subexpressions using the productions of `dsl` and
+/// variables bound at most `max_lam_depth` lambdas up. `set` must have structural hashing so that each subexpression
+/// is stored once however many programs share it. Ill typed subexpressions are pruned as soon as they're built and
+/// rolled back out of `set`. All costs must be positive so that each cost level is finite.
+pub fn enumerate_bottom_up<D: Domain>(set: &mut ExprSet, dsl: &DSL<D>, tp: &SlowType, cost_fn: &ExprCost, max_cost: i32, max_lam_depth: usize) -> Vec<(Idx, i32)> {
+    assert!(set.struct_hash.is_some(), "enumerate_bottom_up() requires a set with struct_hash=true");
+    assert!(cost_fn.cost_app > 0 && cost_fn.cost_lam > 0 && cost_fn.cost_var > 0 && cost_fn.cost_prim_default > 0
+        && cost_fn.cost_prim.values().all(|c| *c > 0), "enumerate_bottom_up() requires positive costs");
+
+    // the leaves by cost, with productions in order of name so enumeration is deterministic
+    let mut leaves: BTreeMap<i32, Vec<Node>> = BTreeMap::new();
+    let mut names: Vec<&Symbol> = dsl.productions.keys().collect();
+    names.sort();
+    for name in names {
+        let cost = *cost_fn.cost_prim.get(name).unwrap_or(&cost_fn.cost_prim_default);
+        leaves.entry(cost).or_default().push(Node::Prim(name.clone()));
+    }
+    for i in 0..max_lam_depth {
+        leaves.entry(cost_fn.cost_var).or_default().push(Node::Var(i as i32));
+    }
+
+    // every well typed subexpression found so far by cost, along with its type
+    let mut bank: BTreeMap<i32, Vec<(Idx, SlowType)>> = BTreeMap::new();
+    let mut analyzed_free_vars = AnalyzedExpr::new(FreeVarAnalysis);
+    let mut found = vec![];
+
+    for cost in 1..=max_cost {
+        let mut nodes: Vec<Node> = leaves.get(&cost).cloned().unwrap_or_default();
+
+        if let Some(bodies) = bank.get(&(cost - cost_fn.cost_lam)) {
+            nodes.extend(bodies.iter().map(|(b, _)| Node::Lam(*b)));
+        }
+
+        nodes.extend(apps_of_cost(&bank, cost, cost_fn));
+
+        let mut level = vec![];
+        for node in nodes {
+            let state = set.save_state();
+            let idx = set.add(node);
+            let Some(e_tp) = infer_open(set.get(idx), dsl, max_lam_depth) else {
+                set.load_state(state);
+                continue
+            };
+            analyzed_free_vars.analyze_to(set, idx);
+            if analyzed_free_vars[idx].is_empty() {
+                let mut ctx = Context::empty();
+                let e_tp = e_tp.instantiate(&mut ctx);
+                let tp = tp.instantiate(&mut ctx);
+                if ctx.unify(&e_tp, &tp).is_ok() {
+                    found.push((idx, cost));
+                }
+            }
+            level.push((idx, e_tp));
+        }
+        if !level.is_empty() {
+            bank.insert(cost, level);
+        }
+    }
+    found
+}
+
+/// every App of two banked expressions that costs exactly `cost` and might typecheck
+fn apps_of_cost(bank: &BTreeMap<i32, Vec<(Idx, SlowType)>>, cost: i32, cost_fn: &ExprCost) -> Vec<Node> {
+    let mut nodes = vec![];
+    for (f_cost, fs) in bank.range(..cost - cost_fn.cost_app) {
+        let Some(xs) = bank.get(&(cost - cost_fn.cost_app - f_cost)) else { continue };
+        for (f, f_tp) in fs {
+            // only functions (or things of unknown type) can be applied
+            let arg_tp = match f_tp {
+                SlowType::Var(_) => None,
+                _ => match f_tp.as_arrow() {
+                    Some((arg_tp, _)) => Some(arg_tp),
+                    None => continue,
+                }
+            };
+            for (x, x_tp) in xs {
+                // type vars are wildcards to might_unify() so it's fine that f_tp and x_tp come from different contexts
+                if arg_tp.map(|arg_tp| Context::might_unify(arg_tp, x_tp)).unwrap_or(true) {
+                    nodes.push(Node::App(*f, *x));
+                }
+            }
+        }
+    }
+    nodes
+}
+
+/// renumber the type variables of `tp` t0, t1, ... in order of first occurrence, so that types that are equal up to
+/// renaming compare equal. `seen` holds the original variables in that order.
+fn canonical_vars(tp: &SlowType, seen: &mut Vec<usize>) -> SlowType {
+    match tp {
+        SlowType::Var(i) => SlowType::Var(seen.iter().position(|j| j == i).unwrap_or_else(|| { seen.push(*i); seen.len() - 1 })),
+        SlowType::Term(name, args) => SlowType::Term(name.clone(), args.iter().map(|arg| canonical_vars(arg, seen)).collect()),
+    }
+}
+
+/// infer the type of a possibly open expression whose free variables are bound at most `max_lam_depth` lambdas
+/// up, returning None if it's ill typed
+fn infer_open<D: Domain>(e: Expr, dsl: &DSL<D>, max_lam_depth: usize) -> Option<SlowType> {
+    let mut ctx = Context::empty();
+    let mut env: VecDeque<SlowType> = (0..max_lam_depth).map(|_| ctx.fresh_type_var()).collect();
+    e.infer(&mut ctx, &mut env, dsl).ok()
+}
+
+/// Budgets for enumerate_top_down()
+#[derive(Debug, Clone)]
+pub struct TopDownConfig {
+    pub max_cost: i32, // programs (and partial programs) costing more than this are pruned
+    pub max_depth: usize, // the deepest hole that may be filled, where the root is at depth 0 and arguments and lambda bodies are one deeper
+    pub max_programs: usize, // stop after finding this many programs
+}
+
+/// a hole in a partial program that's waiting to be filled
+#[derive(Debug, Clone)]
+struct OpenHole {
+    tp: SlowType,
+    env: VecDeque<SlowType>,
+    depth: usize,
+}
+
+/// How the leftmost hole of a partial program was filled: with `node` (a leaf, or Lam(HOLE)) and `num_holes` new
+/// holes for its arguments (or body), after the fills in `prev`. Partial programs share the fills they have in common.
+#[derive(Debug)]
+struct Fill {
+    node: Node,
+    num_holes: usize,
+    prev: Option<Rc<Fill>>,
+}
+
+/// a partial program in the top down search, as the fills made so far (the latest first)
+#[derive(Debug, Clone)]
+struct Partial {
+    fills: Option<Rc<Fill>>,
+    holes: Vec<OpenHole>, // the open holes with the leftmost last
+    ctx: Context,
+    cost: i32,
+}
+
+/// Build the program made by `fills` into a ParentFirst set with expand(). Since each fill was of the leftmost hole
+/// this is the program in preorder.
+fn build_program(fills: &Option<Rc<Fill>>) -> ExprOwned {
+    let mut in_order = vec![];
+    let mut fill = fills;
+    while let Some(f) = fill {
+        in_order.push(f);
+        fill = &f.prev;
+    }
+
+    let mut set = ExprSet::empty(Order::ParentFirst, false, false);
+    let mut root = HOLE;
+    // the node each open hole is a child of (None for the root) with the leftmost last
+    let mut parents: Vec<Option<Idx>> = vec![None];
+    for fill in in_order.into_iter().rev() {
+        let (idx, children) = if let Node::Lam(_) = fill.node {
+            let idx = set.add(fill.node.clone());
+            (idx, vec![idx])
+        } else {
+            // build `(f a1 ... ak)` as k Apps top down, where the right child of the i-th App from the bottom is the hole for `ai`
+            let mut apps: Vec<Idx> = vec![];
+            for _ in 0..fill.num_holes {
+                let app = set.add(Node::App(HOLE,HOLE));
+                if let Some(&above) = apps.last() {
+                    set.get_mut(above).expand(app);
+                }
+                apps.push(app);
+            }
+            let leaf = set.add(fill.node.clone());
+            if let Some(&bottom) = apps.last() {
+                set.get_mut(bottom).expand(leaf);
+            }
+            (apps.first().copied().unwrap_or(leaf), apps)
+        };
+        match parents.pop().unwrap() {
+            Some(parent) => set.get_mut(parent).expand(idx),
+            None => root = idx,
+        }
+        // the top App holds the last argument, so it goes first
+        parents.extend(children.into_iter().map(Some));
+    }
+    ExprOwned::new(set, root)
+}
+
+/// Enumerate closed programs of type `tp` top down and best first: starting from a single hole, repeatedly pop the
+/// cheapest partial program and fill its leftmost hole in every way that typechecks. Holes of arrow type are filled
+/// with a `lam` and every other hole with a production or variable applied to enough arguments (new holes) to have
+/// the hole's type (see DSL::candidates()), so programs come out in eta long form. Partial programs are ordered by the
+/// cost of their filled nodes under `cost_fn` plus the cheapest possible leaf for each open hole, which never
+/// overestimates, so complete programs come out in increasing order of cost. Returns each program along with its cost.
+pub fn enumerate_top_down<D: Domain>(dsl: &DSL<D>, tp: &SlowType, cost_fn: &ExprCost, config: &TopDownConfig) -> Vec<(ExprOwned, i32)> {
+    assert!(cost_fn.cost_app >= 0 && cost_fn.cost_lam >= 0 && cost_fn.cost_var >= 0 && cost_fn.cost_prim_default >= 0
+        && cost_fn.cost_prim.values().all(|c| *c >= 0), "enumerate_top_down() requires nonnegative costs");
+    let cost_of_prim = |p: &Symbol| *cost_fn.cost_prim.get(p).unwrap_or(&cost_fn.cost_prim_default);
+    let min_leaf_cost = dsl.productions.keys().map(cost_of_prim).chain([cost_fn.cost_var]).min().unwrap();
+    let priority = |partial: &Partial| partial.cost + partial.holes.len() as i32 * min_leaf_cost;
+
+    let mut found = vec![];
+    // each partial program is stored once and the queue holds (priority, index) pairs, so ties are broken first come first served
+    let mut partials: Vec<Option<Partial>> = vec![];
+    let mut queue: BinaryHeap<Reverse<(i32, usize)>> = BinaryHeap::new();
+    let mut ctx = Context::empty();
+    let start = Partial {
+        fills: None,
+        holes: vec![OpenHole { tp: tp.instantiate(&mut ctx), env: VecDeque::new(), depth: 0 }],
+        ctx,
+        cost: 0,
+    };
+    queue.push(Reverse((priority(&start), 0)));
+    partials.push(Some(start));
+
+    while let Some(Reverse((_, i))) = queue.pop() {
+        let mut partial = partials[i].take().unwrap();
+        let Some(hole) = partial.holes.pop() else {
+            found.push((build_program(&partial.fills), partial.cost));
+            if found.len() >= config.max_programs {
+                break
+            }
+            continue
+        };
+
+        // each way to fill the hole as (leaf node or Lam(HOLE), cost, holes for its arguments or body, context)
+        let mut fillers: Vec<(Node, i32, Vec<OpenHole>, Context)> = vec![];
+        let hole_tp = hole.tp.apply(&partial.ctx);
+        if let Some((arg_tp, body_tp)) = hole_tp.as_arrow() {
+            let mut env = hole.env.clone();
+            env.push_front(arg_tp.clone());
+            let body = OpenHole { tp: body_tp.clone(), env, depth: hole.depth + 1 };
+            fillers.push((Node::Lam(HOLE), cost_fn.cost_lam, vec![body], partial.ctx.clone()));
+        } else {
+            for candidate in dsl.candidates(&hole_tp, &hole.env, &mut partial.ctx) {
+                // binding the hole's type variable to an arrow would break eta long form
+                if hole_tp.apply(&candidate.ctx).is_arrow() {
+                    continue
+                }
+                let leaf_cost = match &candidate.node {
+                    Node::Prim(p) => cost_of_prim(p),
+                    _ => cost_fn.cost_var,
+                };
+                let cost = leaf_cost + cost_fn.cost_app * candidate.arg_tps.len() as i32;
+                let args = candidate.arg_tps.iter().map(|tp| OpenHole { tp: tp.clone(), env: hole.env.clone(), depth: hole.depth + 1 }).collect();
+                fillers.push((candidate.node, cost, args, candidate.ctx));
+            }
+        }
+
+        for (node, cost, children, ctx) in fillers {
+            if !children.is_empty() && hole.depth + 1 > config.max_depth {
+                continue
+            }
+            let fill = Fill { num_holes: children.len(), node, prev: partial.fills.clone() };
+            let mut holes = partial.holes.clone();
+            holes.extend(children.into_iter().rev());
+            let child = Partial { fills: Some(Rc::new(fill)), holes, ctx, cost: partial.cost + cost };
+            if priority(&child) <= config.max_cost {
+                queue.push(Reverse((priority(&child), partials.len())));
+                partials.push(Some(child));
+            }
+        }
+    }
+    found
+}
+
+/// What enumerate_observational() does with a program that errors on some input. Timeouts aren't covered by
+/// this, see ObservationalConfig.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    Discard, // drop the program entirely
+    AsOutput, // treat erroring as an output of its own, so it's equivalent to anything that errors on the same inputs
+}
+
+/// Budgets and policies for enumerate_observational(). Two limits apply whatever the config says:
+/// - Lambdas take one argument and their bodies contain no lambdas, so eg `(lam (lam (+ $0 $1)))` is never found.
+/// - A program that hits `timelimit` on any input is always discarded, even with ErrorPolicy::AsOutput, since
+///   whether it times out depends on the machine and its load, so it's not a reliable output to compare.
+#[derive(Debug, Clone)]
+pub struct ObservationalConfig {
+    pub max_cost: i32, // programs costing more than this aren't built
+    pub timelimit: Option<Duration>, // per evaluation of a program on one input
+    pub on_error: ErrorPolicy, // for errors other than timeouts
+}
+
+/// Enumerate programs bottom up like enumerate_bottom_up(), but prune by observational equivalence: each candidate is
+/// evaluated on every one of `inputs` (where `$i` is bound to the ith value of each Env, and `input_tps[i]` is its type)
+/// and only the cheapest program of a given type is kept for each distinct vector of outputs, everything else being
+/// rolled back out of `set`. Returns the kept programs whose type unifies with `tp`, in increasing order of cost.
+///
+/// The body of a lambda can't be evaluated on the inputs alone, so bodies (which see the lambda's argument as `$0` and
+/// the inputs as `$1`, `$2`, ...) are enumerated in a bank of their own that's only pruned by type. Each lambda is then
+/// kept or pruned like any other program by its outputs, which are closures, and the programs that apply it (like
+/// `(map (lam (* $0 $0)) $0)`) are pruned by their outputs as usual.
+///
+/// Replacing a subexpression with an observationally equivalent one only preserves the outputs of the larger program
+/// under eager evaluation, so a lazy primitive (eg `if`) can make a pruned program behave differently from the kept
+/// one in some context. Function valued outputs are compared structurally (by their primitive and partial arguments,
+/// or a closure's body) rather than extensionally, so the pruning is conservative there.
+pub fn enumerate_observational<D: Domain>(set: &mut ExprSet, dsl: &DSL<D>, inputs: &[Env<D>], input_tps: &VecDeque<SlowType>, tp: &SlowType, cost_fn: &ExprCost, config: &ObservationalConfig) -> Vec<(Idx, i32)> {
+    assert!(set.struct_hash.is_some(), "enumerate_observational() requires a set with struct_hash=true");
+    assert!(cost_fn.cost_app > 0 && cost_fn.cost_lam > 0 && cost_fn.cost_var > 0 && cost_fn.cost_prim_default > 0
+        && cost_fn.cost_prim.values().all(|c| *c > 0), "enumerate_observational() requires positive costs");
+
+    let mut leaves: BTreeMap<i32, Vec<Node>> = BTreeMap::new();
+    let mut names: Vec<&Symbol> = dsl.productions.keys().collect();
+    names.sort();
+    for name in names {
+        let cost = *cost_fn.cost_prim.get(name).unwrap_or(&cost_fn.cost_prim_default);
+        leaves.entry(cost).or_default().push(Node::Prim(name.clone()));
+    }
+    // lambda bodies have one more variable than programs do
+    let mut body_leaves = leaves.clone();
+    for i in 0..input_tps.len() {
+        leaves.entry(cost_fn.cost_var).or_default().push(Node::Var(i as i32));
+    }
+    for i in 0..=input_tps.len() {
+        body_leaves.entry(cost_fn.cost_var).or_default().push(Node::Var(i as i32));
+    }
+
+    let mut bank: BTreeMap<i32, Vec<(Idx, SlowType)>> = BTreeMap::new();
+    let mut bodies: BTreeMap<i32, Vec<(Idx, SlowType)>> = BTreeMap::new();
+    // the type and outputs (None for an error) of every program kept so far
+    let mut seen: HashSet<(SlowType, Vec<Option<Val<D>>>)> = HashSet::new();
+    let mut found = vec![];
+
+    for cost in 1..=config.max_cost {
+        // bodies are built first so that rolling back a program never rolls back a body
+        if cost + cost_fn.cost_lam <= config.max_cost {
+            let mut nodes: Vec<Node> = body_leaves.get(&cost).cloned().unwrap_or_default();
+            nodes.extend(apps_of_cost(&bodies, cost, cost_fn));
+            let mut level = vec![];
+            for node in nodes {
+                let state = set.save_state();
+                let idx = set.add(node);
+                let mut ctx = Context::empty();
+                let mut env = input_tps.clone();
+                env.push_front(ctx.fresh_type_var());
+                match set.get(idx).infer(&mut ctx, &mut env, dsl) {
+                    Ok(body_tp) => level.push((idx, body_tp.apply(&ctx))),
+                    Err(_) => set.load_state(state),
+                }
+            }
+            if !level.is_empty() {
+                bodies.insert(cost, level);
+            }
+        }
+
+        let mut nodes: Vec<Node> = leaves.get(&cost).cloned().unwrap_or_default();
+        if let Some(level) = bodies.get(&(cost - cost_fn.cost_lam)) {
+            nodes.extend(level.iter().map(|(b, _)| Node::Lam(*b)));
+        }
+        nodes.extend(apps_of_cost(&bank, cost, cost_fn));
+
+        let mut level = vec![];
+        for node in nodes {
+            let state = set.save_state();
+            let idx = set.add(node);
+            let mut ctx = Context::empty();
+            let Ok(e_tp) = set.get(idx).infer(&mut ctx, &mut input_tps.clone(), dsl) else {
+                set.load_state(state);
+                continue
+            };
+            let e_tp = canonical_vars(&e_tp.apply(&ctx), &mut vec![]);
+
+            let mut outputs = Vec::with_capacity(inputs.len());
+            for env in inputs {
+                match set.get(idx).eval(env, dsl, config.timelimit) {
+                    Ok(val) => outputs.push(Some(val)),
+                    Err(err) if err.is_timeout() || config.on_error == ErrorPolicy::Discard => break,
+                    Err(_) => outputs.push(None),
+                }
+            }
+            // anything but a full set of outputs means it was discarded, and otherwise keep only the first (cheapest)
+            if outputs.len() != inputs.len() || !seen.insert((e_tp.clone(), outputs)) {
+                set.load_state(state);
+                continue
+            }
+
+            let mut ctx = Context::empty();
+            let (inst_e_tp, inst_tp) = (e_tp.instantiate(&mut ctx), tp.instantiate(&mut ctx));
+            if ctx.unify(&inst_e_tp, &inst_tp).is_ok() {
+                found.push((idx, cost));
+            }
+            level.push((idx, e_tp));
+        }
+        if !level.is_empty() {
+            bank.insert(cost, level);
+        }
+    }
+    found
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use domains::simple::SimpleVal;
+    use domains::prim_lists::ListVal;
+
+    #[test]
+    fn test_bottom_up() {
+        let dsl = SimpleVal::new_dsl();
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, true);
+        let cost_fn = ExprCost::num_nodes();
+
+        let progs = enumerate_bottom_up(set, &dsl, &"int".parse().unwrap(), &cost_fn, 3, 1);
+        let strs: Vec<String> = progs.iter().map(|(idx, _)| set.get(*idx).to_string()).collect();
+        assert_eq!(strs, vec!["0", "1", "2", "(sum [])"]);
+        assert_eq!(progs.iter().map(|(_, cost)| *cost).collect::<Vec<_>>(), vec![1, 1, 1, 3]);
+
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, true);
+        let tp: SlowType = "int -> int".parse().unwrap();
+        let progs = enumerate_bottom_up(set, &dsl, &tp, &cost_fn, 6, 2);
+        let strs: Vec<String> = progs.iter().map(|(idx, _)| set.get(*idx).to_string()).collect();
+        // constant functions are fine too
+        assert_eq!(strs[..4], ["(lam 0)", "(lam 1)", "(lam 2)", "(lam $0)"]);
+        for s in ["(+ 1)", "(lam (+ $0 1))", "(lam (* $0 $0))"] {
+            assert!(strs.contains(&s.to_string()), "{} missing", s);
+        }
+        for s in ["(lam [])", "(lam (lam $0))", "(+ [])", "(lam $1)"] {
+            assert!(!strs.contains(&s.to_string()), "{} shouldn't be enumerated", s);
+        }
+        // in increasing cost, each stored once, all of the right type
+        assert!(progs.windows(2).all(|w| w[0].1 <= w[1].1));
+        let mut idxs: Vec<Idx> = progs.iter().map(|(idx, _)| *idx).collect();
+        idxs.sort();
+        idxs.dedup();
+        assert_eq!(idxs.len(), progs.len());
+        for (idx, cost) in progs.iter() {
+            assert_eq!(set.get(*idx).cost_rec(&cost_fn), *cost);
+            let mut ctx = Context::empty();
+            let inferred = set.get(*idx).infer(&mut ctx, &mut Default::default(), &dsl).unwrap();
+            assert!(ctx.unify(&inferred, &tp).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_top_down() {
+        let dsl = SimpleVal::new_dsl();
+        let cost_fn = ExprCost::num_nodes();
+        let config = TopDownConfig { max_cost: 5, max_depth: 10, max_programs: 1000 };
+        let strs = |progs: &[(ExprOwned, i32)]| progs.iter().map(|(e, _)| e.to_string()).collect::<Vec<_>>();
+
+        let tp: SlowType = "int".parse().unwrap();
+        let progs = enumerate_top_down(&dsl, &tp, &cost_fn, &config);
+        assert_eq!(strs(&progs)[..4], ["0", "1", "2", "(sum [])"]);
+        assert!(strs(&progs).contains(&"(* 2 1)".to_string()));
+        // everything found bottom up in eta long form and vice versa
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, true);
+        let bottom_up: Vec<String> = enumerate_bottom_up(set, &dsl, &tp, &cost_fn, 5, 1).iter().map(|(idx, _)| set.get(*idx).to_string()).collect();
+        assert!(strs(&progs).iter().all(|s| bottom_up.contains(s)));
+        assert_eq!(bottom_up.len() - progs.len(), bottom_up.iter().filter(|s| s.contains("lam")).count());
+
+        for (e, cost) in progs.iter() {
+            assert_eq!(e.immut().cost_rec(&cost_fn), *cost);
+            assert!(e.set.iter().all(|i| e.set.get(i).node_order_safe()));
+        }
+        assert!(progs.windows(2).all(|w| w[0].1 <= w[1].1));
+
+        // arrows are filled with lambdas
+        let progs = enumerate_top_down(&dsl, &"int -> int".parse().unwrap(), &cost_fn, &TopDownConfig { max_cost: 6, ..config.clone() });
+        assert_eq!(strs(&progs)[..4], ["(lam 0)", "(lam 1)", "(lam 2)", "(lam $0)"]);
+        assert!(strs(&progs).contains(&"(lam (+ $0 $0))".to_string()));
+        assert!(!strs(&progs).contains(&"(+ 1)".to_string()));
+        let progs = enumerate_top_down(&dsl, &"(list int) -> (list int)".parse().unwrap(), &cost_fn, &TopDownConfig { max_cost: 7, ..config.clone() });
+        assert!(strs(&progs).contains(&"(lam (map (lam $0) $0))".to_string()));
+
+        // budgets
+        let progs = enumerate_top_down(&dsl, &tp, &cost_fn, &TopDownConfig { max_programs: 2, ..config.clone() });
+        assert_eq!(strs(&progs), ["0", "1"]);
+        let progs = enumerate_top_down(&dsl, &tp, &cost_fn, &TopDownConfig { max_depth: 0, ..config.clone() });
+        assert_eq!(strs(&progs), ["0", "1", "2"]);
+        let progs = enumerate_top_down(&dsl, &tp, &cost_fn, &TopDownConfig { max_cost: 0, ..config.clone() });
+        assert!(progs.is_empty());
+    }
+
+    #[test]
+    fn test_observational() {
+        let dsl = SimpleVal::new_dsl();
+        let cost_fn = ExprCost::num_nodes();
+        let int: SlowType = "int".parse().unwrap();
+        let config = ObservationalConfig { max_cost: 5, timelimit: None, on_error: ErrorPolicy::Discard };
+        let inputs: Vec<Env<SimpleVal>> = vec![Env::from(vec![1.into()]), Env::from(vec![2.into()])];
+        let input_tps: VecDeque<SlowType> = vec![int.clone()].into();
+
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, true);
+        let progs = enumerate_observational(set, &dsl, &inputs, &input_tps, &int, &cost_fn, &config);
+        let strs: Vec<String> = progs.iter().map(|(idx, _)| set.get(*idx).to_string()).collect();
+        assert_eq!(strs[..4], ["0", "1", "2", "$0"]);
+        // same outputs as something cheaper
+        for s in ["(+ $0 0)", "(+ 1 1)", "(* 1 $0)", "(sum [])"] {
+            assert!(!strs.contains(&s.to_string()), "{} should be pruned", s);
+        }
+        assert_eq!(strs.iter().filter(|s| ["(+ 1 $0)", "(+ $0 1)"].contains(&s.as_str())).count(), 1);
+        assert!(strs.contains(&"(* $0 $0)".to_string()));
+        // every kept program is distinguished by its outputs
+        let outputs: Vec<Vec<Val<SimpleVal>>> = progs.iter()
+            .map(|(idx, _)| inputs.iter().map(|env| set.get(*idx).eval(env, &dsl, None).unwrap()).collect()).collect();
+        for (i, out) in outputs.iter().enumerate() {
+            assert!(!outputs[..i].contains(out), "{} is equivalent to an earlier program", strs[i]);
+        }
+        assert!(progs.windows(2).all(|w| w[0].1 <= w[1].1));
+        for (idx, cost) in progs.iter() {
+            assert_eq!(set.get(*idx).cost_rec(&cost_fn), *cost);
+        }
+
+        // programs that error on some input
+        let dsl = ListVal::new_dsl();
+        let inputs: Vec<Env<ListVal>> = ["[1,2]", "[]"].iter().map(|l| Env::from(vec![dsl.val_of_prim(&(*l).into()).unwrap()])).collect();
+        let input_tps: VecDeque<SlowType> = vec!["list int".parse().unwrap()].into();
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, true);
+        let config = ObservationalConfig { max_cost: 3, ..config };
+        let strs: Vec<String> = enumerate_observational(set, &dsl, &inputs, &input_tps, &int, &cost_fn, &config)
+            .iter().map(|(idx, _)| set.get(*idx).to_string()).collect();
+        assert!(!strs.contains(&"(car $0)".to_string()));
+        assert_eq!(strs[..2], ["0", "1"]);
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, true);
+        let config = ObservationalConfig { on_error: ErrorPolicy::AsOutput, ..config };
+        let strs: Vec<String> = enumerate_observational(set, &dsl, &inputs, &input_tps, &int, &cost_fn, &config)
+            .iter().map(|(idx, _)| set.get(*idx).to_string()).collect();
+        assert!(strs.contains(&"(car $0)".to_string()));
+
+        // programs that need a lambda
+        let dsl = SimpleVal::new_dsl();
+        let inputs: Vec<Env<SimpleVal>> = ["[1,2,3]", "[0,5]"].iter().map(|l| Env::from(vec![dsl.val_of_prim(&(*l).into()).unwrap()])).collect();
+        let input_tps: VecDeque<SlowType> = vec!["list int".parse().unwrap()].into();
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, true);
+        let config = ObservationalConfig { max_cost: 10, on_error: ErrorPolicy::Discard, ..config };
+        let progs = enumerate_observational(set, &dsl, &inputs, &input_tps, &"list int".parse().unwrap(), &cost_fn, &config);
+        let strs: Vec<String> = progs.iter().map(|(idx, _)| set.get(*idx).to_string()).collect();
+        assert!(strs.contains(&"(map (lam (* $0 $0)) $0)".to_string()));
+        assert!(strs.contains(&"(map (lam (sum $1)) $0)".to_string()));
+        // a lambda that's the same as a partial application loses to it, being more expensive
+        assert!(strs.contains(&"(map (+ 1) $0)".to_string()));
+        assert!(!strs.contains(&"(map (lam (+ $0 1)) $0)".to_string()));
+        for (idx, cost) in progs.iter() {
+            assert_eq!(set.get(*idx).cost_rec(&cost_fn), *cost);
+        }
+    }
+}
@@ -1,8 +1,9 @@
 use crate::*;
 
-use std::fmt::{Debug};
+use std::fmt::{self, Debug, Display, Formatter};
 use std::hash::Hash;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::time::{Instant,Duration};
 use serde::{Serialize, Deserialize};
 
@@ -59,25 +60,133 @@ impl<D: Domain> From<&[Val<D>]> for Env<D> {
 /// a value can either be some domain specific value Dom(D) like an Int,
 /// or it can be a primitive function or partially applied primitive function like + or (+ 2)
 /// or it can be a lambda function with some captured env like (lam (* $1 $0)) where $1 may have been captured from
-/// the surrounding code and this whole object may now be passed around
+/// the surrounding code and this whole object may now be passed around. Closures and thunks carry the Origin of their Idx.
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Val<D: Domain> {
     Dom(D),
     PrimFun(CurriedFn<D>), // function ptr, arity, any args that have been partially filled in
-    LamClosure(Idx, Env<D>), // body, captured env
-    Thunk(Idx, Env<D>)
+    LamClosure(Idx, Env<D>, Origin), // body, captured env, expr the body lives in
+    Thunk(Idx, Env<D>, Origin) // unevaluated lazy arg or invention body, its env, expr it lives in
 }
 
+/// Which expression the Idx of a closure or thunk points into. None is the program being
+/// evaluated, while Some(name) is the body of the invention production `name` in the DSL.
+pub type Origin = Option<Symbol>;
+
 pub type VResult<D> = Result<Val<D>,VError>;
-pub type VError = String;
 
+/// Why evaluating a program failed. DSL functions can just return `Err("some message".into())` for a
+/// VError::Domain, and load_args! reports values of the wrong type as a VError::TypeMismatch.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum VError {
+    /// ran past the time limit given to the Evaluator
+    Timeout,
+    /// used up the fuel given to the Evaluator
+    OutOfFuel,
+    /// a value wasn't of the type expected, eg the argument at position `arg` (counting from 0) of the production `prim`
+    TypeMismatch { prim: Option<Symbol>, arg: Option<usize>, msg: String },
+    /// something that isn't a function was applied to an argument
+    ArityError(String),
+    /// a primitive that isn't in the DSL
+    UnknownPrimitive(Symbol),
+    /// a de Bruijn variable `$i` with nothing bound to it in the environment
+    UnboundVar(usize),
+    /// part of the program that can't run, like an IVar, a hole, or a production with no implementation
+    NotExecutable(String),
+    /// the program used more of something than it's allowed, eg invocations of `fix`
+    ResourceLimit(String),
+    /// any other error raised by a DSL function, eg taking the head of an empty list
+    Domain(String),
+}
+
+impl VError {
+    /// a TypeMismatch that load_args! and CurriedFn::apply() fill in the argument and production of
+    pub fn mismatch(msg: &str) -> Self {
+        VError::TypeMismatch { prim: None, arg: None, msg: msg.into() }
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, VError::Timeout)
+    }
+
+    /// record that this happened loading argument `i`, if it's a TypeMismatch that doesn't know yet
+    pub fn at_arg(mut self, i: usize) -> Self {
+        if let VError::TypeMismatch { arg: arg @ None, .. } = &mut self {
+            *arg = Some(i);
+        }
+        self
+    }
+
+    /// record that this happened in the production `name`, if it's a TypeMismatch that doesn't know yet
+    pub fn in_prim(mut self, name: &Symbol) -> Self {
+        if let VError::TypeMismatch { prim: prim @ None, .. } = &mut self {
+            *prim = Some(name.clone());
+        }
+        self
+    }
+}
+
+impl Display for VError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            VError::Timeout => write!(f, "Eval Timeout"),
+            VError::OutOfFuel => write!(f, "Eval Out Of Fuel"),
+            VError::TypeMismatch { prim, arg, msg } => {
+                write!(f, "type mismatch")?;
+                if let Some(arg) = arg {
+                    write!(f, " in argument {}", arg)?;
+                }
+                if let Some(prim) = prim {
+                    write!(f, " of `{}`", prim)?;
+                }
+                write!(f, ": {}", msg)
+            },
+            VError::ArityError(msg) => write!(f, "{}", msg),
+            VError::UnknownPrimitive(p) => write!(f, "Prim `{}` not found", p),
+            VError::UnboundVar(i) => write!(f, "unbound variable ${}", i),
+            VError::NotExecutable(msg) => write!(f, "{}", msg),
+            VError::ResourceLimit(msg) => write!(f, "{}", msg),
+            VError::Domain(msg) => write!(f, "{}", msg),
+        }
+    }
+}
 
+impl std::error::Error for VError {}
+
+impl From<&str> for VError {
+    fn from(msg: &str) -> Self {
+        VError::Domain(msg.into())
+    }
+}
+
+impl From<String> for VError {
+    fn from(msg: String) -> Self {
+        VError::Domain(msg)
+    }
+}
+
+
+
+/// The default Evaluator::max_prim_nesting. Each primitive that applies a closure or forces a thunk (like `fix` or
+/// `map`) starts another run of eval_iterative() on the native stack, so this keeps recursion through primitives
+/// short of overflowing a 2MB thread stack (the default for spawned threads) even in debug builds, however big the
+/// explicit stack gets.
+pub const DEFAULT_MAX_PRIM_NESTING: usize = 100;
 
 #[derive(Debug)]
 pub struct Evaluator<'a, D: Domain> {
     pub expr: Expr<'a>,
     pub data: RefCell<D::Data>,
     pub start_and_timelimit: Option<(Instant, Duration)>,
+    pub fuel: Cell<Option<u64>>, // how much fuel is left, if there's a limit. See charge()
+    pub max_depth: Option<usize>, // the deepest eval_child_in() calls may nest
+    pub depth: Cell<usize>, // how deeply eval_child_in() calls are currently nested
+    pub max_allocated: Option<usize>, // the most the sizes of primitives' results may add up to, see set_max_allocated()
+    pub allocated: Cell<usize>,
+    pub iterative: bool, // evaluate with an explicit stack rather than recursion, see eval_iterative()
+    pub max_prim_nesting: usize, // the most primitive calls may nest when `iterative`, see eval_iterative()
+    pub prim_nesting: Cell<usize>, // how many primitive calls are in progress when `iterative`
+    pub inventions: RefCell<HashMap<Symbol, Val<D>>>, // values of the inventions evaluated so far, see eval_prim()
     pub dsl: &'a DSL<D>,
 }
 
@@ -91,6 +200,15 @@ impl<'a> Expr<'a> {
             expr: self,
             data: Default::default(),
             start_and_timelimit,
+            fuel: Cell::new(None),
+            max_depth: None,
+            depth: Cell::new(0),
+            max_allocated: None,
+            allocated: Cell::new(0),
+            iterative: false,
+            max_prim_nesting: DEFAULT_MAX_PRIM_NESTING,
+            prim_nesting: Cell::new(0),
+            inventions: Default::default(),
             dsl
         }
     }
@@ -131,7 +249,15 @@ impl<D: Domain> CurriedFn<D> {
     pub fn apply(mut self, arg: Val<D>, handle: &Evaluator<D>) -> VResult<D> {
         self.partial_args.push_back(arg);
         if self.partial_args.len() == self.arity {
-            handle.dsl.productions.get(&self.name).unwrap().fn_ptr.unwrap() (self.partial_args, handle)
+            let name = self.name;
+            let production = handle.dsl.productions.get(&name).ok_or_else(|| VError::UnknownPrimitive(name.clone()))?;
+            let fn_ptr = production.fn_ptr.ok_or_else(|| VError::NotExecutable(format!("`{}` has no fn_ptr to call", name)))?;
+            handle.charge(1)?;
+            let val = fn_ptr(self.partial_args, handle).map_err(|e| e.in_prim(&name))?;
+            if let Val::Dom(d) = &val {
+                handle.allocate(d.size())?;
+            }
+            Ok(val)
         } else {
             Ok(Val::PrimFun(self))
         }
@@ -142,18 +268,18 @@ impl<D: Domain> Val<D> {
     pub fn dom(self) -> Result<D,VError> {
         match self {
             Val::Dom(d) => Ok(d),
-            _ => Err("Val::unwrap_dom: not a domain value".into())
+            _ => Err(VError::mismatch("Val::unwrap_dom: not a domain value"))
         }
     }
     #[inline(always)]
     pub fn unthunk(&self, handle: &Evaluator<D>) -> VResult<D> {
-        if let Val::Thunk(idx,env) = self {
-            return handle.eval_child(*idx, &env)
+        if let Val::Thunk(idx,env,origin) = self {
+            return handle.eval_child_in(origin, *idx, env)
         }
         // else {
         //     Ok(self.clone())
         // }
-        panic!("unthunk(): not a thunk");
+        Err(VError::mismatch("unthunk(): not a thunk"))
     }
     
 }
@@ -179,14 +305,15 @@ impl<D: Domain> FromVal<D> for Val<D> {
 impl<'a, D: Domain> Evaluator<'a,D> {
     // apply a function (Val) to an argument (LazyVal)
     pub fn apply(&self, f: Val<D>, x: Val<D>) -> VResult<D> {
+        self.charge(1)?;
         match f {
             Val::PrimFun(f) => f.apply(x, self),
-            Val::LamClosure(f, mut env) => {
+            Val::LamClosure(f, mut env, origin) => {
                 env.push_front(x);
-                self.eval_child(f, &env)
+                self.eval_child_in(&origin, f, &env)
             }
             // _ => Err(format!("Expected function or closure, got {:?}", f)),
-            _ => Err("Expected function or closure".into()),
+            _ => Err(VError::ArityError("Expected function or closure".into())),
         }
     }
 
@@ -194,45 +321,187 @@ impl<'a, D: Domain> Evaluator<'a,D> {
         self.start_and_timelimit = Some((Instant::now(), timeout))
     }
 
-    /// eval a subexpression in an environment
-    pub fn eval_child(&self, child: Idx, env: &Env<D>) -> VResult<D> {
+    /// Limit evaluation to `fuel` steps, which unlike a timeout gives the same result on every machine. Each
+    /// eval_child_in(), apply(), and call to a primitive's DSLFn costs one, and primitives can charge more for
+    /// expensive work with charge(). Works alongside a timeout, whichever runs out first.
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel.set(Some(fuel))
+    }
+
+    /// the fuel left, if there's a limit
+    pub fn fuel_left(&self) -> Option<u64> {
+        self.fuel.get()
+    }
+
+    /// Limit how deeply evaluation may nest (roughly the depth of the native stack it uses), so that deep recursion
+    /// errors with a VError::ResourceLimit instead of overflowing the stack
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = Some(max_depth)
+    }
+
+    /// Limit the cumulative size (see Domain::size()) of the values returned by primitives during evaluation, erroring
+    /// with a VError::ResourceLimit past it. Every result counts, including ones a primitive passes through or slices
+    /// out of its arguments (like `car` and `cdr`), so this is a budget on the total size of results rather than on the
+    /// memory in use: eg building a list of length n one `cons` at a time counts about n²/2.
+    pub fn set_max_allocated(&mut self, max_allocated: usize) {
+        self.max_allocated = Some(max_allocated)
+    }
+
+    /// count `size` more towards the max_allocated budget, erroring if that's past the limit. This is done automatically
+    /// for the values primitives return, but they can also report other big allocations themselves.
+    pub fn allocate(&self, size: usize) -> Result<(), VError> {
+        let allocated = self.allocated.get() + size;
+        self.allocated.set(allocated);
+        match self.max_allocated {
+            Some(max) if allocated > max => Err(VError::ResourceLimit(format!("Exceeded max allocated elements of {}", max))),
+            _ => Ok(()),
+        }
+    }
+
+    /// use up `amount` fuel, erroring with VError::OutOfFuel if there isn't that much left
+    pub fn charge(&self, amount: u64) -> Result<(), VError> {
+        if let Some(fuel) = self.fuel.get() {
+            if fuel < amount {
+                self.fuel.set(Some(0));
+                return Err(VError::OutOfFuel)
+            }
+            self.fuel.set(Some(fuel - amount));
+        }
+        Ok(())
+    }
+
+    /// error if the time limit has passed
+    #[inline(always)]
+    pub(crate) fn check_timeout(&self) -> Result<(), VError> {
         if let Some((start_time, duration)) = &self.start_and_timelimit {
             if start_time.elapsed() >= *duration {
-                return Err("Eval Timeout".to_string());
+                return Err(VError::Timeout);
             }
         }
-        let val = match self.expr.get_node(child) {
+        Ok(())
+    }
+
+    /// whether the next argument `func` takes should be passed as a Thunk rather than evaluated first
+    #[inline(always)]
+    pub(crate) fn next_arg_lazy(&self, func: &CurriedFn<D>) -> Result<bool, VError> {
+        let production = self.dsl.productions.get(&func.name).ok_or_else(|| VError::UnknownPrimitive(func.name.clone()))?;
+        Ok(production.lazy_args.contains(&func.partial_args.len()))
+    }
+
+    /// the expression that Idxs with a given origin point into
+    #[inline(always)]
+    pub(crate) fn expr_of(&self, origin: &Origin) -> Result<Expr<'a>, VError> {
+        match origin {
+            None => Ok(self.expr),
+            Some(name) => self.dsl.productions.get(name)
+                .ok_or_else(|| VError::UnknownPrimitive(name.clone()))?
+                .body.as_ref()
+                .map(|body| body.immut())
+                .ok_or_else(|| VError::NotExecutable(format!("`{}` is not an invention", name))),
+        }
+    }
+
+    /// The value of the primitive `p`, except that an invention that hasn't been evaluated yet is returned as
+    /// the Thunk of its body. Since an invention's body is closed it's evaluated at most once per Evaluator,
+    /// and whoever forces the Thunk should record the value in `self.inventions`.
+    #[inline(always)]
+    pub(crate) fn eval_prim(&self, p: &Symbol) -> VResult<D> {
+        if let Some(v) = self.inventions.borrow().get(p) {
+            return Ok(v.clone())
+        }
+        self.dsl.val_of_prim(p).ok_or_else(|| VError::UnknownPrimitive(p.clone()))
+    }
+
+    /// eval a subexpression of the program in an environment
+    pub fn eval_child(&self, child: Idx, env: &Env<D>) -> VResult<D> {
+        self.eval_child_in(&None, child, env)
+    }
+
+    /// eval a subexpression in an environment, where `child` is an Idx into the
+    /// expression given by `origin`
+    pub fn eval_child_in(&self, origin: &Origin, child: Idx, env: &Env<D>) -> VResult<D> {
+        if self.iterative {
+            return self.eval_iterative(origin, child, env)
+        }
+        self.eval_in(self.expr_of(origin)?, origin, child, env)
+    }
+
+    /// eval_child_in() once `origin` has been resolved to `expr`, which its subexpressions share
+    fn eval_in(&self, expr: Expr<'a>, origin: &Origin, child: Idx, env: &Env<D>) -> VResult<D> {
+        let depth = self.depth.get();
+        if let Some(max_depth) = self.max_depth {
+            if depth >= max_depth {
+                return Err(max_depth_error(max_depth));
+            }
+        }
+        self.depth.set(depth + 1);
+        let res = self.eval_node(expr, origin, child, env);
+        self.depth.set(depth);
+        res
+    }
+
+    /// eval_in() without the depth tracking
+    fn eval_node(&self, expr: Expr<'a>, origin: &Origin, child: Idx, env: &Env<D>) -> VResult<D> {
+        self.check_timeout()?;
+        self.charge(1)?;
+        if child == HOLE {
+            return Err(hole_error())
+        }
+        let val = match expr.get_node(child) {
             Node::Var(i) => {
-                env.get(*i as usize).clone()
+                env.env.get(*i as usize).ok_or(VError::UnboundVar(*i as usize))?.clone()
+            }
+            Node::IVar(i) => {
+                return Err(VError::NotExecutable(format!("attempting to execute a #{} ivar", i)))
             }
-            Node::IVar(_) => {
-                panic!("attempting to execute a #i ivar")
+            Node::Hole(id) => {
+                return Err(VError::NotExecutable(format!("attempting to execute a ?{} hole", id)))
             }
             Node::App(f,x) => {
-                let f_val = self.eval_child(*f, env)?;
+                let f_val = self.eval_in(expr, origin, *f, env)?;
 
                 let x_val = if let Val::PrimFun(func) = &f_val {
-                    if self.dsl.productions.get(&func.name).unwrap().lazy_args.contains(&func.partial_args.len()) {
-                        Val::Thunk(*x, env.clone())
+                    if self.next_arg_lazy(func)? {
+                        Val::Thunk(*x, env.clone(), origin.clone())
                     } else {
-                        self.eval_child(*x, env)?
+                        self.eval_in(expr, origin, *x, env)?
                     }
                 } else {
-                    self.eval_child(*x, env)?
+                    self.eval_in(expr, origin, *x, env)?
                 };
                 
                 self.apply(f_val, x_val)?
             }
             Node::Prim(p) => {
-                match self.dsl.val_of_prim(p) {
-                    Some(v) => v,
-                    None => panic!("Prim `{}` not found",p),
+                match self.eval_prim(p)? {
+                    // inventions are thunks of their body
+                    v @ Val::Thunk(..) => {
+                        let v = v.unthunk(self)?;
+                        self.inventions.borrow_mut().insert(p.clone(), v.clone());
+                        v
+                    },
+                    v => v,
                 }
             }
             Node::Lam(b) => {
-                Val::LamClosure(*b, env.clone())
+                Val::LamClosure(*b, env.clone(), origin.clone())
             }
         };
         Ok(val)
     }
 }
+
+/// the error for evaluating a `??` HOLE child
+pub(crate) fn hole_error() -> VError {
+    VError::NotExecutable("attempting to execute a ?? hole".into())
+}
+
+/// the error for nesting primitive calls deeper than Evaluator::max_prim_nesting
+pub(crate) fn prim_nesting_error(max_prim_nesting: usize) -> VError {
+    VError::ResourceLimit(format!("Exceeded max primitive nesting of {}", max_prim_nesting))
+}
+
+/// the error for nesting deeper than Evaluator::max_depth
+pub(crate) fn max_depth_error(max_depth: usize) -> VError {
+    VError::ResourceLimit(format!("Exceeded max eval depth of {}", max_depth))
+}
@@ -26,6 +26,7 @@ pub enum Node where
     IVar(i32), // abstraction ("invention") variable
     App(Idx,Idx), // f, x
     Lam(Idx), // body
+    Hole(HoleId), // typed hole, see ExprSet::holes for its type
 }
 
 /// An untyped lambda calculus expression or set of expressions
@@ -35,6 +36,12 @@ pub struct ExprSet {
     pub spans: Option<Vec<Range<Idx>>>,
     pub order: Order,
     pub struct_hash: Option<FxHashMap<Node,Idx>>,
+    #[serde(skip)]
+    pub edit_log: Vec<(Idx,Node)>, // (idx, node before the edit) for each expand()/unexpand() made while a save_state() checkpoint is live, used by load_state()
+    #[serde(skip)]
+    checkpointed: bool, // whether save_state() has been called since the last commit()
+    #[serde(default)]
+    pub holes: Vec<Option<HoleInfo>>, // holes[id] is the expected type and env of Node::Hole(id) if known
 }
 
 /// the ordering of nodes in an ExprSet
@@ -81,6 +88,7 @@ impl ExprOwned {
         self.set.iter().map(|i|
             match self.set.get(i).node() {
                 Node::IVar(_) => cost_fn.cost_ivar,
+                Node::Hole(_) => 0,
                 Node::Var(_) => cost_fn.cost_var,
                 Node::Prim(p) => *cost_fn.cost_prim.get(p).unwrap_or(&cost_fn.cost_prim_default),
                 Node::App(_, _) => cost_fn.cost_app,
@@ -128,7 +136,7 @@ impl ExprSet {
             assert_eq!(order,Order::ChildFirst, "struct_hash=true requires order=ChildFirst");
         }
         let struct_hash = if struct_hash { Some(Default::default()) } else { None };
-        ExprSet { nodes: vec![], spans, order, struct_hash }
+        ExprSet { nodes: vec![], spans, order, struct_hash, edit_log: vec![], checkpointed: false, holes: vec![] }
     }
     /// add a Node to an ExprSet
     pub fn add(&mut self, node: Node) -> Idx {
@@ -143,10 +151,11 @@ impl ExprSet {
         let idx = self.nodes.len();
         // push on a new span if we're tracking spans
         if let Some(spans) = &mut self.spans {
+            let child_span = |c: Idx| if c == HOLE { idx .. idx+1 } else { spans[c].clone() };
             let span = match node {
-                Node::Var(_) | Node::Prim(_) | Node::IVar(_) => idx .. idx+1,
-                Node::App(f, x) => min(min(spans[f].start,spans[x].start),idx) .. max(max(spans[f].end,spans[x].end),idx+1),
-                Node::Lam(b) => min(spans[b].start,idx) .. max(spans[b].end,idx+1)
+                Node::Var(_) | Node::Prim(_) | Node::IVar(_) | Node::Hole(_) => idx .. idx+1,
+                Node::App(f, x) => min(min(child_span(f).start,child_span(x).start),idx) .. max(max(child_span(f).end,child_span(x).end),idx+1),
+                Node::Lam(b) => min(child_span(b).start,idx) .. max(child_span(b).end,idx+1)
             };
             spans.push(span);
         }
@@ -164,15 +173,15 @@ impl ExprSet {
     }
 
     #[inline(always)]
-    pub fn get(&self, idx: Idx) -> Expr {
+    pub fn get(&self, idx: Idx) -> Expr<'_> {
         Expr { set: self, idx }
     }
     #[inline(always)]
-    pub fn get_mut(&mut self, idx: Idx) -> ExprMut {
+    pub fn get_mut(&mut self, idx: Idx) -> ExprMut<'_> {
         ExprMut { set: self, idx }
     }
     #[inline(always)]
-    pub fn hole(&self) -> Expr {
+    pub fn hole(&self) -> Expr<'_> {
         Expr { set: self, idx: HOLE }
     }
     /// number of Nodes in ExprSet
@@ -183,14 +192,179 @@ impl ExprSet {
     pub fn is_empty(&self) -> bool {
         self.nodes.is_empty()
     }
-    /// truncate the underlying vector of Nodes
+    /// truncate the underlying vector of Nodes, along with their spans and struct hash entries.
+    /// Doesn't undo any edits made by expand() etc, see load_state() for that.
     pub fn truncate(&mut self, len: usize) {
+        if len >= self.nodes.len() {
+            return
+        }
+        if let Some(struct_hash) = &mut self.struct_hash {
+            for (idx, node) in self.nodes.iter().enumerate().skip(len) {
+                if struct_hash.get(node) == Some(&idx) {
+                    struct_hash.remove(node);
+                }
+            }
+        }
+        if let Some(spans) = &mut self.spans {
+            spans.truncate(len);
+        }
         self.nodes.truncate(len);
     }
+
+    /// save the current state so it can be rolled back to with load_state(). In-place edits are only logged
+    /// (so they can be undone) from the first save_state() until the next commit().
+    pub fn save_state(&mut self) -> (usize,usize,usize) {
+        self.checkpointed = true;
+        (self.nodes.len(), self.edit_log.len(), self.holes.len())
+    }
+
+    /// keep all edits made so far, dropping the log of in-place edits and no longer logging new ones until
+    /// the next save_state(). Any states saved before this can still roll back added nodes and holes but
+    /// no longer undo in-place edits.
+    pub fn commit(&mut self) {
+        self.edit_log.clear();
+        self.checkpointed = false;
+    }
+
+    /// roll back to a state from save_state(), undoing any expand()/unexpand()/fill_hole() edits and removing any
+    /// nodes (and their spans and struct hash entries) and typed holes added since then
+    pub fn load_state(&mut self, state: (usize,usize,usize)) {
+        // undo edits first so the nodes match their struct hash keys again before truncating
+        while self.edit_log.len() > state.1 {
+            let (idx, node) = self.edit_log.pop().unwrap();
+            self.nodes[idx] = node;
+        }
+        self.truncate(state.0);
+        self.holes.truncate(state.2);
+    }
     /// returns an iterator over the Idxs from 0 to the max Idx
     pub fn iter(&self) -> impl ExactSizeIterator<Item=Idx> {
         0..self.nodes.len()
     }
+
+    /// Garbage collect by copying only the nodes reachable from `roots` into a new ExprSet with the
+    /// same order, span tracking and structural hashing. Nodes keep their relative order so
+    /// the order invariant is preserved. Also returns a table where `remap[old_idx]` is the new Idx
+    /// of a node or None if it was dropped, which can be used to update any side tables (see
+    /// AnalyzedExpr::remap()). Holes stay holes.
+    pub fn compact(&self, roots: &[Idx]) -> (ExprSet, Vec<Option<Idx>>) {
+        // mark everything reachable from the roots
+        let mut reachable = vec![false; self.len()];
+        let mut worklist: Vec<Idx> = roots.iter().copied().filter(|&i| i != HOLE).collect();
+        while let Some(i) = worklist.pop() {
+            if reachable[i] {
+                continue
+            }
+            reachable[i] = true;
+            worklist.extend(self.get(i).children().filter(|&c| c != HOLE));
+        }
+
+        // num_before[i] is the number of reachable nodes before i, which is the new idx of i if its reachable
+        let mut num_before = Vec::with_capacity(self.len() + 1);
+        num_before.push(0);
+        for &r in reachable.iter() {
+            num_before.push(num_before.last().unwrap() + r as usize);
+        }
+        let remap: Vec<Option<Idx>> = self.iter().map(|i| if reachable[i] { Some(num_before[i]) } else { None }).collect();
+        let new_idx = |i: Idx| if i == HOLE { HOLE } else { remap[i].unwrap() };
+
+        let nodes: Vec<Node> = self.iter().filter(|&i| reachable[i]).map(|i| match &self[i] {
+            Node::Prim(_) | Node::Var(_) | Node::IVar(_) | Node::Hole(_) => self[i].clone(),
+            Node::App(f, x) => Node::App(new_idx(*f), new_idx(*x)),
+            Node::Lam(b) => Node::Lam(new_idx(*b)),
+        }).collect();
+
+        // an old span still covers the node and all its descendants, so keeping just the reachable nodes
+        // within it gives a new span
+        let spans = self.spans.as_ref().map(|spans| self.iter().filter(|&i| reachable[i])
+            .map(|i| num_before[spans[i].start] .. num_before[spans[i].end])
+            .collect());
+
+        let struct_hash = self.struct_hash.as_ref().map(|_| nodes.iter().cloned().enumerate().map(|(i,node)| (node,i)).collect());
+
+        let set = ExprSet { nodes, spans, order: self.order.clone(), struct_hash, edit_log: vec![], checkpointed: false, holes: self.holes.clone() };
+        (set, remap)
+    }
+
+    /// Convert to a new ExprSet with the given order (and structural hashing if `struct_hash`, which
+    /// requires ChildFirst), keeping span tracking if this set has it. Works from any order, including
+    /// topologically sorting an Order::Any set. Also returns a table where `remap[old_idx]` is the new Idx of
+    /// each node (with struct_hash multiple old nodes may map to the same new one). Holes stay holes
+    /// and the typed hole table is kept.
+    /// Panics if the set contains a cycle.
+    pub fn reorder(&self, order: Order, struct_hash: bool) -> (ExprSet, Vec<Idx>) {
+        let mut set = ExprSet::empty(Order::ChildFirst, self.spans.is_some(), struct_hash);
+        set.holes = self.holes.clone();
+        let mut remap: Vec<Idx> = vec![HOLE; self.len()];
+
+        if self.order == Order::ChildFirst {
+            // already topologically sorted
+            for i in self.iter() {
+                remap[i] = set.add(self.remap_node(i, &remap));
+            }
+        } else {
+            // postorder traversal, with an explicit stack so deep expressions dont overflow
+            let mut in_progress = vec![false; self.len()];
+            for root in self.iter() {
+                let mut stack = vec![(root, false)];
+                while let Some((i, children_done)) = stack.pop() {
+                    if remap[i] != HOLE {
+                        continue
+                    }
+                    if children_done {
+                        remap[i] = set.add(self.remap_node(i, &remap));
+                        in_progress[i] = false;
+                        continue
+                    }
+                    assert!(!in_progress[i], "cycle encountered in reorder() at node {}", i);
+                    in_progress[i] = true;
+                    stack.push((i, true));
+                    // reversed so that left children get added first
+                    let children: Vec<Idx> = self.get(i).children().filter(|&c| c != HOLE && remap[c] == HOLE).collect();
+                    stack.extend(children.into_iter().rev().map(|c| (c, false)));
+                }
+            }
+        }
+
+        match order {
+            Order::ChildFirst => {},
+            Order::Any => set.order = Order::Any,
+            Order::ParentFirst => {
+                assert!(!struct_hash, "struct_hash=true requires order=ChildFirst");
+                let len = set.len();
+                let flip = |i: Idx| if i == HOLE { HOLE } else { len - 1 - i };
+                set.nodes.reverse();
+                for node in set.nodes.iter_mut() {
+                    match node {
+                        Node::Prim(_) | Node::Var(_) | Node::IVar(_) | Node::Hole(_) => {},
+                        Node::App(f, x) => { *f = flip(*f); *x = flip(*x); },
+                        Node::Lam(b) => *b = flip(*b),
+                    }
+                }
+                if let Some(spans) = &mut set.spans {
+                    spans.reverse();
+                    for span in spans.iter_mut() {
+                        *span = len - span.end .. len - span.start;
+                    }
+                }
+                set.order = Order::ParentFirst;
+                for i in remap.iter_mut() {
+                    *i = flip(*i);
+                }
+            }
+        }
+        (set, remap)
+    }
+
+    /// the node at `i` with its children remapped (holes stay holes)
+    fn remap_node(&self, i: Idx, remap: &[Idx]) -> Node {
+        let new_idx = |c: Idx| if c == HOLE { HOLE } else { remap[c] };
+        match &self[i] {
+            Node::Prim(_) | Node::Var(_) | Node::IVar(_) | Node::Hole(_) => self[i].clone(),
+            Node::App(f, x) => Node::App(new_idx(*f), new_idx(*x)),
+            Node::Lam(b) => Node::Lam(new_idx(*b)),
+        }
+    }
 }
 
 
@@ -212,7 +386,7 @@ impl<'a> Expr<'a> {
     }
     pub fn children(&self) -> impl Iterator<Item=Idx> {
         match self.node() {
-            Node::Var(_) | Node::Prim(_) | Node::IVar(_) => vec![].into_iter(),
+            Node::Var(_) | Node::Prim(_) | Node::IVar(_) | Node::Hole(_) => vec![].into_iter(),
             Node::App(f, x) => vec![*f, *x].into_iter(),
             Node::Lam(b) => vec![*b].into_iter()
         }
@@ -256,6 +430,7 @@ impl<'a> Expr<'a> {
         let res = self.iter_span().map(|i|
             match self.set.get(i).node() {
                 Node::IVar(_) => cost_fn.cost_ivar,
+                Node::Hole(_) => 0,
                 Node::Var(_) => cost_fn.cost_var,
                 Node::Prim(p) => *cost_fn.cost_prim.get(p).unwrap_or(&cost_fn.cost_prim_default),
                 Node::App(_, _) => cost_fn.cost_app,
@@ -271,6 +446,7 @@ impl<'a> Expr<'a> {
     pub fn cost_rec(&self, cost_fn: &ExprCost) -> i32 {
         match self.node() {
             Node::IVar(_) => cost_fn.cost_ivar,
+            Node::Hole(_) => 0,
             Node::Var(_) => cost_fn.cost_var,
             Node::Prim(p) => *cost_fn.cost_prim.get(p).unwrap_or(&cost_fn.cost_prim_default),
             Node::App(f, x) => {
@@ -283,44 +459,53 @@ impl<'a> Expr<'a> {
     }
 
     /// copy this Expr onto the end of another ExprSet by copying the span and upshifting
-    /// all indices appropriately. This is order-aware.
+    /// all indices appropriately. This is order-aware: when copying between ChildFirst and
+    /// ParentFirst sets the copied nodes are reversed and their children and spans remapped.
+    /// Holes are preserved.
     pub fn copy_span(&self, other_set: &mut ExprSet) -> Idx {
-        let shift: i32 = other_set.iter().len() as i32 - self.get_span().unwrap().start as i32;
-        // extend everything on while shfiting it
-        other_set.nodes.extend(self.iter_span().map(|i| {
+        // ensure if we're Any then they are not Any
+        if self.set.order == Order::Any && other_set.order != Order::Any {
+            panic!("breaking order invariant: reorder() the Any set first")
+        }
+
+        // reverse order if we have opposite orders
+        let reverse = self.set.order == Order::ChildFirst && other_set.order == Order::ParentFirst
+            || self.set.order == Order::ParentFirst && other_set.order == Order::ChildFirst;
+
+        let span = self.get_span().unwrap();
+        let base = other_set.len();
+        // the Idx in other_set that the node at `i` gets copied to
+        let new_idx = |i: Idx| -> Idx {
+            if i == HOLE {
+                HOLE
+            } else if reverse {
+                base + span.end - 1 - i
+            } else {
+                base + i - span.start
+            }
+        };
+        let new_span = |span: Span| -> Span {
+            let (a, b) = (new_idx(span.start), new_idx(span.end - 1));
+            min(a, b) .. max(a, b) + 1
+        };
+        let old_idxs: Vec<Idx> = if reverse { span.clone().rev().collect() } else { span.clone().collect() };
+
+        // extend everything on while shifting it
+        other_set.nodes.extend(old_idxs.iter().map(|&i| {
             let node = self.get_node(i);
             match node {
-                Node::Prim(_) | Node::Var(_) | Node::IVar(_) => node.clone(),
-                Node::App(f, x) => Node::App((*f as i32 + shift) as usize, (*x as i32 + shift) as usize),
-                Node::Lam(b) => Node::Lam((*b as i32 + shift) as usize),
+                Node::Prim(_) | Node::Var(_) | Node::IVar(_) | Node::Hole(_) => node.clone(),
+                Node::App(f, x) => Node::App(new_idx(*f), new_idx(*x)),
+                Node::Lam(b) => Node::Lam(new_idx(*b)),
             }
         }));
 
         // shift all the spans and extend them on
         if let Some(other_spans) = &mut other_set.spans {
-            other_spans.extend(self.iter_span().map(|i| {
-                let span = self.get(i).get_span().unwrap();
-                (span.start as i32 + shift) as usize .. (span.end as i32 + shift) as usize
-            }))
+            other_spans.extend(old_idxs.iter().map(|&i| new_span(self.get(i).get_span().unwrap())))
         }
 
-        // reverse order if we have opposite orders
-        if self.set.order == Order::ChildFirst && other_set.order == Order::ParentFirst
-            || self.set.order == Order::ParentFirst && other_set.order == Order::ChildFirst
-        {
-            let len = other_set.nodes.len();
-            other_set.nodes[len - self.iter_span().len()..].reverse();
-            if let Some(other_spans) = &mut other_set.spans {
-                other_spans[len - self.iter_span().len()..].reverse();
-            }
-        }
-
-        // ensure if we're Any then they are not Any
-        if self.set.order == Order::Any && other_set.order != Order::Any {
-            panic!("breaking order invariant")
-        }
-
-        (self.idx as i32 + shift) as usize
+        new_idx(self.idx)
     }
 
     /// copy this Expr onto the end of another ExprSet by upshifting
@@ -332,7 +517,7 @@ impl<'a> Expr<'a> {
         assert_eq!(self.set.order, other_set.order);
         fn helper(e: Expr, other_set: &mut ExprSet) -> Idx {
             match e.node() {
-                Node::Prim(_) | Node::Var(_) | Node::IVar(_) => {
+                Node::Prim(_) | Node::Var(_) | Node::IVar(_) | Node::Hole(_) => {
                     other_set.add(e.node().clone())
                 }
                 Node::App(f, x) => {
@@ -353,7 +538,7 @@ impl<'a> Expr<'a> {
     /// return true if the node at this expr obeys the defined node order
     pub fn node_order_safe(&self) -> bool {
         match self.node() {
-            Node::Prim(_) | Node::Var(_) | Node::IVar(_) => true,
+            Node::Prim(_) | Node::Var(_) | Node::IVar(_) | Node::Hole(_) => true,
             Node::App(f, x) => match self.set.order {
                 Order::ChildFirst => (*f == HOLE || *f < self.idx) && (*x == HOLE || *x < self.idx),
                 Order::ParentFirst => (*f == HOLE || *f > self.idx) && (*x == HOLE || *x > self.idx),
@@ -377,7 +562,7 @@ impl<'a> Expr<'a> {
 
 impl<'a> ExprMut<'a> {
     #[inline(always)]
-    pub fn get(&mut self, idx: Idx) -> ExprMut {
+    pub fn get(&mut self, idx: Idx) -> ExprMut<'_> {
         ExprMut { set: self.set, idx }
     }
     #[inline(always)]
@@ -398,9 +583,20 @@ impl<'a> ExprMut<'a> {
         Expr {set: self.set, idx: self.idx}
     }
 
+    /// record the current node so the upcoming in-place edit can be undone by load_state()
+    #[inline(always)]
+    pub(crate) fn log_edit(&mut self) {
+        if !self.set.checkpointed {
+            return
+        }
+        let node = self.node().clone();
+        self.set.edit_log.push((self.idx, node));
+    }
+
     /// Fill the first hole at this node with the pointer `idx`. Panics if there is no hole or
     /// if this is not a Lam or App. Prefers filling the left hole of an App over the right.
     pub fn expand(&mut self, idx: Idx) {
+        self.log_edit();
         match self.node() {
             Node::App(x,y) => {
                 if *x == HOLE {
@@ -422,6 +618,7 @@ impl<'a> ExprMut<'a> {
     /// expands a Lam or the righthand side of an App to point to `idx`, but never expands
     /// the lefthand side of an App. Panics if no hole is found.
     pub fn expand_right(&mut self, idx: Idx) {
+        self.log_edit();
         match self.node() {
             Node::App(_,y) => {
                 assert_eq!(*y, HOLE, "invalid expand_right() on non-hole");
@@ -438,6 +635,7 @@ impl<'a> ExprMut<'a> {
 
     /// inverse of expand(), but doesn't panic in the case that something is already a hole.
     pub fn unexpand(&mut self) {
+        self.log_edit();
         match self.node() {
             Node::App(x,y) => {
                 if *y != HOLE {
@@ -457,6 +655,7 @@ impl<'a> ExprMut<'a> {
     /// inverse of expand_right(), but doesn't panic in the case that something is already a hole. Will never
     /// unexpand the lefthand side of an App.
     pub fn unexpand_right(&mut self) {
+        self.log_edit();
         match self.node() {
             Node::App(_,y) => {
                 if *y != HOLE {
@@ -483,7 +682,7 @@ impl<'a> ExprMut<'a> {
         match self.node().clone() {
             Node::Prim(_) => self.idx,
             Node::Var(i) => if i >= init_depth { self.set.add(Node::Var(i+incr_by)) } else { self.idx },
-            Node::IVar(_) => self.idx,
+            Node::IVar(_) | Node::Hole(_) => self.idx,
             Node::App(f, x) => {
                 let f = self.get(f).shift(incr_by, init_depth, analyzed_free_vars);
                 let x = self.get(x).shift(incr_by, init_depth, analyzed_free_vars);
@@ -495,6 +694,64 @@ impl<'a> ExprMut<'a> {
             },
         }
     }
+
+    /// capture-avoiding substitution of `replacement` for each free $var in this expr. The
+    /// replacement is upshifted each time we go under a Lam so that its own free variables
+    /// still refer to the same binders. No other variables are shifted (eg when doing beta
+    /// reduction the caller should downshift the result afterwards). Subtrees where $var is
+    /// not free are left untouched and shared with the original.
+    pub fn subst(&mut self, var: i32, replacement: Idx, analyzed_free_vars: &mut AnalyzedExpr<FreeVarAnalysis>) -> Idx {
+        analyzed_free_vars.analyze_to(self.set, self.idx);
+        if !analyzed_free_vars[self.idx].contains(&var) {
+            return self.idx; // nothing to substitute
+        }
+
+        match self.node().clone() {
+            Node::Prim(_) => self.idx,
+            Node::Var(_) => replacement, // must be $var since it's free here
+            Node::IVar(_) | Node::Hole(_) => self.idx,
+            Node::App(f, x) => {
+                let f = self.get(f).subst(var, replacement, analyzed_free_vars);
+                let x = self.get(x).subst(var, replacement, analyzed_free_vars);
+                self.set.add(Node::App(f, x))
+            },
+            Node::Lam(b) => {
+                let replacement = self.get(replacement).shift(1, 0, analyzed_free_vars);
+                let b = self.get(b).subst(var+1, replacement, analyzed_free_vars);
+                self.set.add(Node::Lam(b))
+            },
+        }
+    }
+
+    /// instantiate an invention body by replacing each #i in this expr with `args[i]`. Each argument
+    /// is upshifted by the number of Lams between the root and the #i it replaces, so free variables
+    /// in the arguments keep referring to binders outside of the invention. Subtrees without
+    /// any #i are left untouched and shared with the original. Panics if some #i has no argument.
+    pub fn instantiate_ivars(&mut self, args: &[Idx], analyzed_ivars: &mut AnalyzedExpr<IVarAnalysis>, analyzed_free_vars: &mut AnalyzedExpr<FreeVarAnalysis>) -> Idx {
+        fn helper(e: &mut ExprMut, depth: i32, args: &[Idx], analyzed_ivars: &mut AnalyzedExpr<IVarAnalysis>, analyzed_free_vars: &mut AnalyzedExpr<FreeVarAnalysis>) -> Idx {
+            analyzed_ivars.analyze_to(e.set, e.idx);
+            if analyzed_ivars[e.idx].is_empty() {
+                return e.idx; // no ivars to instantiate
+            }
+            match e.node().clone() {
+                Node::Prim(_) | Node::Var(_) | Node::Hole(_) => e.idx,
+                Node::IVar(i) => {
+                    let arg = *args.get(i as usize).unwrap_or_else(|| panic!("#{} has no corresponding argument (only {} given)", i, args.len()));
+                    e.get(arg).shift(depth, 0, analyzed_free_vars)
+                },
+                Node::App(f, x) => {
+                    let f = helper(&mut e.get(f), depth, args, analyzed_ivars, analyzed_free_vars);
+                    let x = helper(&mut e.get(x), depth, args, analyzed_ivars, analyzed_free_vars);
+                    e.set.add(Node::App(f, x))
+                },
+                Node::Lam(b) => {
+                    let b = helper(&mut e.get(b), depth+1, args, analyzed_ivars, analyzed_free_vars);
+                    e.set.add(Node::Lam(b))
+                },
+            }
+        }
+        helper(self, 0, args, analyzed_ivars, analyzed_free_vars)
+    }
 }
 
 // struct ExprIter<'a> {
@@ -597,9 +854,8 @@ mod tests {
         // here
         for i in set.get(e1).iter_span() {
             let bonus = set.len() as i32;
-            match set.get_mut(i).node() {
-                Node::Var(i) => {*i += bonus},
-                _ => {}
+            if let Node::Var(i) = set.get_mut(i).node() {
+                *i += bonus
             }
         }
 
@@ -673,4 +929,214 @@ mod tests {
 
 
     }
+
+    #[test]
+    fn test_save_load_state() {
+        // top down: rolling back expand()s along with the nodes they point to
+        let e = &mut ExprSet::empty(Order::ParentFirst, false, false);
+        let app1 = e.add(Node::App(HOLE,HOLE));
+        let app2 = e.add(Node::App(HOLE,HOLE));
+        let plus = e.add(Node::Prim("+".into()));
+        e.get_mut(app1).expand(app2);
+        e.get_mut(app2).expand(plus);
+        assert_eq!(e.get(app1).to_string(), "(+ ?? ??)");
+        let state = e.save_state();
+
+        let two = e.add(Node::Prim("2".into()));
+        e.get_mut(app2).expand(two);
+        let lam = e.add(Node::Lam(HOLE));
+        e.get_mut(app1).expand(lam);
+        let three = e.add(Node::Prim("3".into()));
+        e.get_mut(lam).expand(three);
+        assert_eq!(e.get(app1).to_string(), "(+ 2 (lam 3))");
+
+        e.load_state(state);
+        assert_eq!(e.get(app1).to_string(), "(+ ?? ??)");
+        assert_eq!(e.len(), 3);
+
+        // edits are only logged while a checkpoint is live
+        e.commit();
+        let two = e.add(Node::Prim("2".into()));
+        e.get_mut(app2).expand(two);
+        assert!(e.edit_log.is_empty());
+        let state = e.save_state();
+        let lam = e.add(Node::Lam(HOLE));
+        e.get_mut(app1).expand(lam);
+        assert_eq!(e.edit_log.len(), 1);
+        e.load_state(state);
+        assert_eq!(e.get(app1).to_string(), "(+ 2 ??)");
+
+        // structural hashing: rolled back nodes must not be handed out again
+        let mut e = ExprSet::empty(Order::ChildFirst, true, true);
+        let foo = e.parse_extend("(foo bar)").unwrap();
+        let state = e.save_state();
+        let baz = e.parse_extend("(foo baz)").unwrap();
+        assert_eq!(e.parse_extend("(foo baz)").unwrap(), baz);
+        e.load_state(state);
+        assert_eq!(e.len(), 3);
+        assert_eq!(e.spans.as_ref().unwrap().len(), 3);
+        assert_eq!(e.parse_extend("(foo bar)").unwrap(), foo);
+        let baz = e.parse_extend("(baz foo)").unwrap();
+        assert!(baz < e.len());
+        assert_eq!(e.get(baz).to_string(), "(baz foo)");
+        assert_eq!(e.struct_hash.as_ref().unwrap().len(), e.len());
+    }
+
+    #[test]
+    fn test_compact() {
+        for (order, struct_hash) in [(Order::ChildFirst, false), (Order::ChildFirst, true), (Order::ParentFirst, false), (Order::Any, false)] {
+            let mut set = ExprSet::empty(order.clone(), true, struct_hash);
+            let mut fv = AnalyzedExpr::new(FreeVarAnalysis);
+            let e1 = set.parse_extend("(foo (lam (bar $0 $1)))").unwrap();
+            set.parse_extend("(this is garbage)").unwrap();
+            let e2 = set.parse_extend("(lam (foo $0))").unwrap();
+            set.parse_extend("(more garbage)").unwrap();
+            if order == Order::ChildFirst {
+                fv.analyze(&set);
+            }
+
+            let (new_set, remap) = set.compact(&[e1, e2]);
+            let (new_e1, new_e2) = (remap[e1].unwrap(), remap[e2].unwrap());
+            assert_eq!(new_set.get(new_e1).to_string(), "(foo (lam (bar $0 $1)))");
+            assert_eq!(new_set.get(new_e2).to_string(), "(lam (foo $0))");
+            assert_eq!(new_set.order, order);
+            assert!(new_set.iter().all(|i| new_set.get(i).node_order_safe()));
+            assert_eq!(remap.iter().filter(|i| i.is_some()).count(), new_set.len());
+            assert_eq!(new_set.spans.as_ref().unwrap().len(), new_set.len());
+            for i in new_set.iter() {
+                assert!(new_set.get(i).get_span().unwrap().contains(&i));
+            }
+            if struct_hash {
+                // foo and $0 are shared between the two roots
+                assert_eq!(new_set.len(), 10);
+                assert_eq!(new_set.struct_hash.as_ref().unwrap().len(), new_set.len());
+            } else {
+                assert_eq!(new_set.len(), 12);
+                assert_eq!(new_set.get(new_e1).cost_span(&ExprCost::num_nodes()), 8);
+            }
+            if order == Order::ChildFirst {
+                fv.remap(&remap);
+                assert_eq!(fv[new_e1], AnalyzedExpr::new(FreeVarAnalysis).analyze_get(new_set.get(new_e1)).clone());
+                assert_eq!(fv[new_e2], AnalyzedExpr::new(FreeVarAnalysis).analyze_get(new_set.get(new_e2)).clone());
+            }
+        }
+    }
+
+    #[test]
+    fn test_reorder() {
+        fn check(set: &ExprSet, idx: Idx, expected: &str) {
+            assert_eq!(set.get(idx).to_string(), expected);
+            assert!(set.iter().all(|i| set.get(i).node_order_safe()));
+            if let Some(spans) = &set.spans {
+                assert_eq!(spans.len(), set.len());
+                assert!(set.iter().all(|i| spans[i].contains(&i)));
+            }
+        }
+
+        // a ParentFirst set built top down, which can then be analyzed once converted to ChildFirst
+        let mut e = ExprSet::empty(Order::ParentFirst, false, false);
+        let app1 = e.add(Node::App(HOLE,HOLE));
+        let app2 = e.add(Node::App(HOLE,HOLE));
+        let plus = e.add(Node::Prim("+".into()));
+        let lam = e.add(Node::Lam(HOLE));
+        e.get_mut(app1).expand(app2);
+        e.get_mut(app2).expand(plus);
+        e.get_mut(app1).expand(lam);
+        let (partial, remap) = e.reorder(Order::ChildFirst, false);
+        check(&partial, remap[app1], "(+ ?? (lam ??))");
+        let var = e.add(Node::Var(0));
+        e.get_mut(lam).expand(var);
+        e.get_mut(app2).expand(var);
+        let (child_first, remap) = e.reorder(Order::ChildFirst, false);
+        check(&child_first, remap[app1], "(+ $0 (lam $0))");
+        assert_eq!(AnalyzedExpr::new(FreeVarAnalysis).analyze_get(child_first.get(remap[app1])), &vec![0].into_iter().collect::<FxHashSet<i32>>());
+        let (hashed, remap) = e.reorder(Order::ChildFirst, true);
+        check(&hashed, remap[app1], "(+ $0 (lam $0))");
+        assert_eq!(hashed.len(), 5);
+
+        // an Any set with children stored after parents and in no particular order
+        let mut e = ExprSet::empty(Order::Any, false, false);
+        let app = e.add(Node::App(HOLE,HOLE));
+        let foo = e.add(Node::Prim("foo".into()));
+        let lam = e.add(Node::Lam(HOLE));
+        let bar = e.add(Node::Prim("bar".into()));
+        e.get_mut(app).expand(foo);
+        e.get_mut(app).expand(lam);
+        e.get_mut(lam).expand(bar);
+        for order in [Order::ChildFirst, Order::ParentFirst, Order::Any] {
+            let (set, remap) = e.reorder(order.clone(), false);
+            assert_eq!(set.order, order);
+            check(&set, remap[app], "(foo (lam bar))");
+        }
+
+        // round trips with spans
+        let mut e = ExprSet::empty(Order::ChildFirst, true, false);
+        let prog = "(foo (lam (bar $0 $1)) (foo baz))";
+        let idx = e.parse_extend(prog).unwrap();
+        let (parent_first, remap) = e.reorder(Order::ParentFirst, false);
+        check(&parent_first, remap[idx], prog);
+        assert_eq!(parent_first.get(remap[idx]).cost_span(&ExprCost::num_nodes()), 12);
+        let (child_first, remap2) = parent_first.reorder(Order::ChildFirst, false);
+        check(&child_first, remap2[remap[idx]], prog);
+        let (hashed, remap) = e.reorder(Order::ChildFirst, true);
+        check(&hashed, remap[idx], prog);
+        assert_eq!(hashed.len(), 11);
+    }
+
+    #[test]
+    fn test_subst() {
+        fn assert_subst(set: &mut ExprSet, e: &str, var: i32, replacement: &str, expected: &str) {
+            let mut fv = AnalyzedExpr::new(FreeVarAnalysis);
+            let e = set.parse_extend(e).unwrap();
+            let replacement = set.parse_extend(replacement).unwrap();
+            let res = set.get_mut(e).subst(var, replacement, &mut fv);
+            assert_eq!(set.get(res).to_string(), expected.to_string());
+        }
+
+        for struct_hash in [false, true] {
+            let set = &mut ExprSet::empty(Order::ChildFirst, true, struct_hash);
+            assert_subst(set, "$0", 0, "(foo bar)", "(foo bar)");
+            assert_subst(set, "$1", 0, "(foo bar)", "$1");
+            assert_subst(set, "(+ $0 $1)", 1, "3", "(+ $0 3)");
+            // bound occurrences are untouched
+            assert_subst(set, "(lam $0)", 0, "3", "(lam $0)");
+            assert_subst(set, "(lam ($0 $1))", 0, "3", "(lam ($0 3))");
+            // the replacement is upshifted under each lambda so it isn't captured
+            assert_subst(set, "(lam (lam ($2 $1)))", 0, "($0 $3)", "(lam (lam ($2 $5 $1)))");
+            assert_subst(set, "(foo #0 (lam #0))", 0, "3", "(foo #0 (lam #0))");
+        }
+
+        // closed subtrees are shared rather than copied
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, false);
+        let mut fv = AnalyzedExpr::new(FreeVarAnalysis);
+        let e = set.parse_extend("((foo bar) $0)").unwrap();
+        let three = set.parse_extend("3").unwrap();
+        let res = set.get_mut(e).subst(0, three, &mut fv);
+        assert_eq!(set.get(res).left().idx, set.get(e).left().idx);
+    }
+
+    #[test]
+    fn test_instantiate_ivars() {
+        fn assert_instantiate(set: &mut ExprSet, body: &str, args: &[&str], expected: &str) {
+            let mut ivars = AnalyzedExpr::new(IVarAnalysis);
+            let mut fv = AnalyzedExpr::new(FreeVarAnalysis);
+            let body = set.parse_extend(body).unwrap();
+            let args: Vec<Idx> = args.iter().map(|arg| set.parse_extend(arg).unwrap()).collect();
+            let res = set.get_mut(body).instantiate_ivars(&args, &mut ivars, &mut fv);
+            assert_eq!(set.get(res).to_string(), expected.to_string());
+        }
+
+        for struct_hash in [false, true] {
+            let set = &mut ExprSet::empty(Order::ChildFirst, true, struct_hash);
+            assert_instantiate(set, "(+ #0 #1)", &["1", "(foo 2)"], "(+ 1 (foo 2))");
+            assert_instantiate(set, "(+ #1 #1)", &["1", "2"], "(+ 2 2)");
+            assert_instantiate(set, "(lam (+ #0 #1))", &["1", "2"], "(lam (+ 1 2))");
+            assert_instantiate(set, "(foo $0)", &["1"], "(foo $0)");
+            // free vars in the args are shifted by the number of binders they move under
+            assert_instantiate(set, "(lam (+ #0 $0))", &["$0"], "(lam (+ $1 $0))");
+            assert_instantiate(set, "(#0 (lam (lam #0)))", &["$0"], "($0 (lam (lam $2)))");
+            // bound vars in the args are left alone
+            assert_instantiate(set, "(lam #0)", &["(lam $0)"], "(lam (lam $0))");
+        }
+    }
 }
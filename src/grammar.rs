@@ -0,0 +1,407 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use serde::{Serialize, Deserialize};
+
+use crate::*;
+
+/// A unigram probabilistic grammar over the programs of a DSL, in the style of DreamCoder: each production has an
+/// unnormalized log weight, and so do variables (as a group) and `lam`. At each node of a program the weights are
+/// normalized over just the choices that could produce a term of the type needed there, where a choice is `lam` or
+/// a production or variable together with how many arguments it's applied to (see DSL::candidates()). So when the
+/// needed type is a type variable, a production that fits with 0, 1 or 2 arguments makes three choices of its weight.
+#[derive(Debug, Clone)]
+pub struct Grammar<'a, D: Domain> {
+    pub dsl: &'a DSL<D>,
+    pub log_probs: HashMap<Symbol, f32>, // by production name, missing productions are never chosen
+    pub log_variable: f32, // shared evenly by all the variables that fit at a node
+    pub log_lam: f32,
+}
+
+/// The weights of a Grammar without the DSL they're for, eg to save to JSON. Production weights are keyed by name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GrammarWeights {
+    pub log_probs: BTreeMap<Symbol, f32>,
+    pub log_variable: f32,
+    pub log_lam: f32,
+}
+
+/// how often each choice was made across some programs, and the choices that were available at each node
+#[derive(Debug, Clone, Default)]
+struct Uses {
+    actual: HashMap<Symbol, f32>,
+    actual_variable: f32,
+    actual_lam: f32,
+    nodes: Vec<Choices>,
+}
+
+/// The choices at a node, from DSL::candidates(): how many times each production appears (once per number of
+/// arguments it can be applied to), how many distinct variables there are and how many times they appear in total,
+/// and whether `lam` is a choice
+#[derive(Debug, Clone)]
+struct Choices {
+    prims: Vec<(Symbol, f32)>,
+    num_vars: usize,
+    var_entries: usize,
+    lam: bool,
+}
+
+impl<'a, D: Domain> Grammar<'a, D> {
+    /// the grammar that weighs every production, `lam`, and variables all the same
+    pub fn uniform(dsl: &'a DSL<D>) -> Self {
+        Grammar {
+            dsl,
+            log_probs: dsl.productions.keys().map(|name| (name.clone(), 0.)).collect(),
+            log_variable: 0.,
+            log_lam: 0.,
+        }
+    }
+
+    /// Estimate weights from a corpus of closed programs, each with the type it was requested at, by maximum likelihood.
+    /// Starting from the uniform grammar, each of `iterations` rounds sets the weight of each production (and of
+    /// variables and `lam`) to the log of how many times it was chosen over how many times it was expected to be
+    /// offered: the sum, over every node, of how many of the choices there it makes (see log_likelihood()) divided by
+    /// the total weight of the choices there under the current weights. `pseudocounts` is added to both. Each round is
+    /// a minorize-maximize step, so with no pseudocounts the log likelihood of the corpus never goes down. The types
+    /// along the way are found just as in log_likelihood(), and programs the grammar can't produce at all are skipped.
+    pub fn fit(dsl: &'a DSL<D>, corpus: &[(Expr, SlowType)], pseudocounts: f32, iterations: usize) -> Self {
+        // the choices at each node don't depend on the weights, so any grammar can find them
+        let mut grammar = Grammar::uniform(dsl);
+        let mut uses = Uses::default();
+        for (e, tp) in corpus {
+            let mut prog_uses = Uses::default();
+            let mut ctx = Context::empty();
+            let tp = tp.instantiate(&mut ctx);
+            if grammar.walk(*e, &tp, &mut VecDeque::new(), &mut ctx, &mut Some(&mut prog_uses)).is_finite() {
+                uses.add(prog_uses);
+            }
+        }
+
+        let log_ratio = |actual: f32, possible: f32| {
+            if possible + pseudocounts == 0. { 0. } else { (actual + pseudocounts).ln() - (possible + pseudocounts).ln() }
+        };
+        for _ in 0..iterations {
+            let mut possible: HashMap<Symbol, f32> = HashMap::new();
+            let mut possible_variable = 0.;
+            let mut possible_lam = 0.;
+            for choices in uses.nodes.iter() {
+                let log_z = grammar.log_normalizer(choices);
+                if !log_z.is_finite() {
+                    continue
+                }
+                for (name, n) in choices.prims.iter() {
+                    *possible.entry(name.clone()).or_default() += (n.ln() - log_z).exp();
+                }
+                if choices.num_vars > 0 {
+                    possible_variable += (choices.var_share().ln() - log_z).exp();
+                }
+                if choices.lam {
+                    possible_lam += (-log_z).exp();
+                }
+            }
+            grammar = Grammar {
+                dsl,
+                log_probs: dsl.productions.keys().map(|name| {
+                    let get = |counts: &HashMap<Symbol, f32>| counts.get(name).copied().unwrap_or(0.);
+                    (name.clone(), log_ratio(get(&uses.actual), get(&possible)))
+                }).collect(),
+                log_variable: log_ratio(uses.actual_variable, possible_variable),
+                log_lam: log_ratio(uses.actual_lam, possible_lam),
+            };
+        }
+        grammar
+    }
+
+    /// a grammar over `dsl` with saved weights, where productions missing from `weights` are never chosen
+    pub fn from_weights(dsl: &'a DSL<D>, weights: &GrammarWeights) -> Self {
+        Grammar {
+            dsl,
+            log_probs: weights.log_probs.iter().map(|(name, w)| (name.clone(), *w)).collect(),
+            log_variable: weights.log_variable,
+            log_lam: weights.log_lam,
+        }
+    }
+
+    pub fn weights(&self) -> GrammarWeights {
+        GrammarWeights {
+            log_probs: self.log_probs.iter().map(|(name, w)| (name.clone(), *w)).collect(),
+            log_variable: self.log_variable,
+            log_lam: self.log_lam,
+        }
+    }
+
+    /// the unnormalized log weight of a production
+    pub fn log_prob(&self, name: &Symbol) -> f32 {
+        self.log_probs.get(name).copied().unwrap_or(f32::NEG_INFINITY)
+    }
+
+    /// The log probability of generating the closed expression `e` when asked for something of type `tp`, where type
+    /// variables in `tp` may be bound to anything. Each node is a choice between `lam` (if the type needed there could be
+    /// an arrow) and every production or variable that can be applied to some number of arguments to get the needed type
+    /// (see DSL::candidates()), and the node's probability is its weight normalized over those choices. Expressions the
+    /// grammar can't produce get `f32::NEG_INFINITY`: ill typed ones, ones using primitives that aren't productions
+    /// (like the literals from Domain::val_of_prim_fallback()), and ones that apply a `lam` directly. Holes and IVars
+    /// are not choices so they contribute nothing.
+    pub fn log_likelihood(&self, e: Expr, tp: &SlowType) -> f32 {
+        let mut ctx = Context::empty();
+        let tp = tp.instantiate(&mut ctx);
+        self.log_likelihood_in(e, &tp, &mut VecDeque::new(), &mut ctx)
+    }
+
+    /// log_likelihood() of a possibly open expression in the environment `env` (where `env[i]` is the type of `$i`)
+    /// relative to the type bindings in `ctx`, which are extended with whatever the expression's types require
+    pub fn log_likelihood_in(&self, e: Expr, tp: &SlowType, env: &mut VecDeque<SlowType>, ctx: &mut Context) -> f32 {
+        self.walk(e, tp, env, ctx, &mut None)
+    }
+
+    /// log_likelihood_in() that also counts the choices made and the choices available into `uses`
+    fn walk(&self, e: Expr, tp: &SlowType, env: &mut VecDeque<SlowType>, ctx: &mut Context, uses: &mut Option<&mut Uses>) -> f32 {
+        if e.idx == HOLE {
+            return 0.
+        }
+        let tp = tp.apply(ctx);
+        if let Node::Hole(_) | Node::IVar(_) = e.node() {
+            return 0.
+        }
+
+        let candidates = self.dsl.candidates(&tp, env, ctx);
+        let choices = Choices::new(&candidates, tp.is_arrow() || matches!(tp, SlowType::Var(_)));
+        let log_z = self.log_normalizer(&choices);
+        let num_vars = choices.num_vars;
+        let lam_ok = choices.lam;
+        if let Some(uses) = uses {
+            uses.nodes.push(choices);
+        }
+
+        if let Node::Lam(b) = e.node() {
+            if !lam_ok {
+                return f32::NEG_INFINITY
+            }
+            let arg_tp = ctx.fresh_type_var();
+            let body_tp = ctx.fresh_type_var();
+            if ctx.unify(&tp, &SlowType::arrow(arg_tp.clone(), body_tp.clone())).is_err() {
+                return f32::NEG_INFINITY
+            }
+            if let Some(uses) = uses {
+                uses.actual_lam += 1.;
+            }
+            env.push_front(arg_tp);
+            let ll = self.log_lam - log_z + self.walk(e.get(*b), &body_tp, env, ctx, uses);
+            env.pop_front();
+            return ll
+        }
+
+        // split into the head and the arguments it's applied to
+        let mut head = e.idx;
+        let mut args = vec![];
+        while let Node::App(f, x) = e.get_node(head) {
+            args.push(*x);
+            head = *f;
+        }
+        args.reverse();
+
+        let head = e.get_node(head);
+        let Some(candidate) = candidates.into_iter().find(|c| &c.node == head && c.arg_tps.len() == args.len()) else {
+            return f32::NEG_INFINITY
+        };
+        let mut ll = match head {
+            Node::Prim(p) => self.log_prob(p),
+            Node::Var(_) => self.log_variable - (num_vars as f32).ln(),
+            _ => return f32::NEG_INFINITY,
+        } - log_z;
+        if let Some(uses) = uses {
+            match head {
+                Node::Prim(p) => *uses.actual.entry(p.clone()).or_default() += 1.,
+                _ => uses.actual_variable += 1.,
+            }
+        }
+
+        *ctx = candidate.ctx;
+        for (arg, arg_tp) in args.into_iter().zip(candidate.arg_tps) {
+            if ll == f32::NEG_INFINITY {
+                break
+            }
+            ll += self.walk(e.get(arg), &arg_tp, env, ctx, uses);
+        }
+        ll
+    }
+
+    /// the log of the total weight of the choices at a node, where variables split the weight for variables evenly
+    fn log_normalizer(&self, choices: &Choices) -> f32 {
+        let mut weights: Vec<f32> = choices.prims.iter().map(|(p, n)| self.log_prob(p) + n.ln()).collect();
+        if choices.num_vars > 0 {
+            weights.push(self.log_variable + choices.var_share().ln());
+        }
+        if choices.lam {
+            weights.push(self.log_lam);
+        }
+        logsumexp(&weights)
+    }
+}
+
+impl Choices {
+    /// the choices among `candidates`, plus `lam` if `lam_ok`
+    fn new(candidates: &[Candidate], lam_ok: bool) -> Self {
+        let mut choices = Choices { prims: vec![], num_vars: 0, var_entries: 0, lam: lam_ok };
+        // candidates with the same node are adjacent, one per number of arguments
+        for (i, candidate) in candidates.iter().enumerate() {
+            let repeat = i > 0 && candidates[i - 1].node == candidate.node;
+            match &candidate.node {
+                Node::Prim(_) if repeat => choices.prims.last_mut().unwrap().1 += 1.,
+                Node::Prim(p) => choices.prims.push((p.clone(), 1.)),
+                _ => {
+                    choices.var_entries += 1;
+                    if !repeat {
+                        choices.num_vars += 1;
+                    }
+                },
+            }
+        }
+        choices
+    }
+
+    /// how many times over the weight for variables is offered, since each variable gets an even share of it
+    fn var_share(&self) -> f32 {
+        self.var_entries as f32 / self.num_vars as f32
+    }
+}
+
+impl Uses {
+    fn add(&mut self, other: Uses) {
+        for (name, n) in other.actual.iter() {
+            *self.actual.entry(name.clone()).or_default() += n;
+        }
+        self.actual_variable += other.actual_variable;
+        self.actual_lam += other.actual_lam;
+        self.nodes.extend(other.nodes);
+    }
+}
+
+/// log(sum(exp(x) for x in xs)), computed stably
+fn logsumexp(xs: &[f32]) -> f32 {
+    let max = xs.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    if max == f32::NEG_INFINITY {
+        return max
+    }
+    max + xs.iter().map(|x| (x - max).exp()).sum::<f32>().ln()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use domains::simple::SimpleVal;
+
+    #[test]
+    fn test_log_likelihood() {
+        let dsl = SimpleVal::new_dsl();
+        let grammar = Grammar::uniform(&dsl);
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, false);
+        let ll = |set: &mut ExprSet, s: &str, tp: &str| {
+            let e = set.parse_extend(s).unwrap();
+            grammar.log_likelihood(set.get(e), &tp.parse().unwrap())
+        };
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+
+        // an int is one of 0 1 2 + * sum
+        assert!(close(ll(set, "0", "int"), -(6f32).ln()));
+        assert!(close(ll(set, "(+ 1 (sum []))", "int"), 3. * -(6f32).ln() - (2f32).ln()));
+        // an int -> int is a lam or a partially applied + or *, and its body can also be $0
+        assert!(close(ll(set, "(lam $0)", "int -> int"), -(3f32).ln() - (7f32).ln()));
+        assert!(close(ll(set, "(+ 1)", "int -> int"), -(3f32).ln() - (6f32).ln()));
+        // the two variables split the weight for variables
+        assert!(close(ll(set, "(lam (lam $1))", "int -> int -> int"), -(3f32).ln() - (3f32).ln() - (7f32).ln() - (2f32).ln()));
+        // type variables in the request can be anything
+        assert!(ll(set, "(lam $0)", "t0 -> t0").is_finite());
+        assert!(ll(set, "[]", "t0").is_finite());
+
+        // things the grammar can't produce
+        assert_eq!(ll(set, "(+ [] 1)", "int"), f32::NEG_INFINITY);
+        assert_eq!(ll(set, "(lam $0)", "int"), f32::NEG_INFINITY);
+        assert_eq!(ll(set, "3", "int"), f32::NEG_INFINITY);
+        assert_eq!(ll(set, "((lam $0) 1)", "int"), f32::NEG_INFINITY);
+        // holes are free
+        assert!(close(ll(set, "(+ 1 ?0)", "int"), 2. * -(6f32).ln()));
+
+        // reweighting
+        let mut grammar = Grammar::uniform(&dsl);
+        grammar.log_probs.insert("0".into(), (4f32).ln());
+        let e = set.parse_extend("0").unwrap();
+        assert!(close(grammar.log_likelihood(set.get(e), &"int".parse().unwrap()), (4f32 / 9.).ln()));
+        grammar.log_probs.remove(&Symbol::from("0"));
+        assert_eq!(grammar.log_likelihood(set.get(e), &"int".parse().unwrap()), f32::NEG_INFINITY);
+
+        // when any type will do, each production and variable can be chosen with any number of arguments that fits,
+        // and the probabilities of all those choices (found by leaving the arguments as holes) add up to 1
+        let grammar = Grammar::uniform(&dsl);
+        let tp: SlowType = "t0".parse().unwrap();
+        let env: VecDeque<SlowType> = vec!["int -> int".parse().unwrap(), "int".parse().unwrap()].into();
+        let mut ctx = Context::empty();
+        let tp = tp.instantiate(&mut ctx);
+        let candidates = dsl.candidates(&tp, &env, &mut ctx.clone());
+        assert_eq!(candidates.iter().filter(|c| c.node == Node::Prim("+".into())).count(), 3);
+        assert_eq!(candidates.iter().filter(|c| c.node == Node::Var(0)).count(), 2);
+        let mut progs: Vec<String> = candidates.iter().map(|c| {
+            let args: String = (0..c.arg_tps.len()).map(|i| format!(" ?{}", i)).collect();
+            format!("({}{})", c.node, args)
+        }).collect();
+        progs.push("(lam ?0)".into());
+        let total: f32 = progs.iter().map(|s| {
+            let e = set.parse_extend(s).unwrap();
+            grammar.log_likelihood_in(set.get(e), &tp, &mut env.clone(), &mut ctx.clone()).exp()
+        }).sum();
+        assert!(close(total, 1.), "total probability {}", total);
+    }
+
+    #[test]
+    fn test_fit() {
+        let dsl = SimpleVal::new_dsl();
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, false);
+        let progs: Vec<(Idx, SlowType)> = [
+            ("(+ 1 1)", "int"),
+            ("(+ (+ 1 2) 0)", "int"),
+            ("(lam (+ $0 1))", "int -> int"),
+            ("(lam (sum (map (lam (+ $0 $0)) $0)))", "list int -> int"),
+            ("(+ 3 3)", "int"), // 3 isn't a production so this is skipped
+        ].iter().map(|(s, tp)| (set.parse_extend(s).unwrap(), tp.parse().unwrap())).collect();
+        let corpus: Vec<(Expr, SlowType)> = progs.iter().map(|(e, tp)| (set.get(*e), tp.clone())).collect();
+
+        let grammar = Grammar::fit(&dsl, &corpus, 1., 20);
+        assert!(grammar.log_prob(&"+".into()) > grammar.log_prob(&"*".into()));
+        assert!(grammar.log_prob(&"1".into()) > grammar.log_prob(&"2".into()));
+        // every arrow was filled with a lam
+        assert!(grammar.log_lam > grammar.log_prob(&"+".into()));
+
+        // the fitted grammar likes the corpus more than the uniform one does
+        let uniform = Grammar::uniform(&dsl);
+        let total = |g: &Grammar<SimpleVal>| corpus[..4].iter().map(|(e, tp)| g.log_likelihood(*e, tp)).sum::<f32>();
+        assert!(total(&grammar) > total(&uniform));
+
+        // with no pseudocounts the corpus log likelihood never goes down from one round to the next
+        let lls: Vec<f32> = (0..30).map(|i| total(&Grammar::fit(&dsl, &corpus, 0., i))).collect();
+        for (i, pair) in lls.windows(2).enumerate() {
+            assert!(pair[1] >= pair[0] - 1e-4, "round {} went from {} to {}", i + 1, pair[0], pair[1]);
+        }
+        assert!(lls[29] > lls[1] + 1e-2);
+        // and it converges to the maximum, where each weight is chosen as often as the grammar expects it to be: "+"
+        // was chosen 5 times over all the nodes where it was one of the choices, some of them more than once
+        let fitted = Grammar::fit(&dsl, &corpus, 0., 500);
+        let mut uses = Uses::default();
+        for (e, tp) in corpus[..4].iter() {
+            let mut ctx = Context::empty();
+            let tp = tp.instantiate(&mut ctx);
+            fitted.walk(*e, &tp, &mut VecDeque::new(), &mut ctx, &mut Some(&mut uses));
+        }
+        let expected: f32 = uses.nodes.iter().map(|choices| {
+            let n = choices.prims.iter().find(|(p, _)| p == &Symbol::from("+")).map_or(0., |(_, n)| *n);
+            (fitted.log_prob(&"+".into()) + n.ln() - fitted.log_normalizer(choices)).exp()
+        }).sum();
+        assert!((expected - 5.).abs() < 1e-2, "expected {} uses of +", expected);
+
+        // weights save to JSON keyed by production name
+        let json = serde_json::to_string(&grammar.weights()).unwrap();
+        assert!(json.contains("\"+\":"));
+        let weights: GrammarWeights = serde_json::from_str(&json).unwrap();
+        assert_eq!(weights, grammar.weights());
+        let loaded = Grammar::from_weights(&dsl, &weights);
+        assert_eq!(total(&loaded), total(&grammar));
+    }
+}
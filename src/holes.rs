@@ -0,0 +1,269 @@
+use std::collections::VecDeque;
+use serde::{Serialize, Deserialize};
+
+use crate::*;
+
+/// identifies a typed hole within an ExprSet, see Node::Hole
+pub type HoleId = usize;
+
+/// What's known about a typed hole: the type the expression filling it must have, and the
+/// types of the de Bruijn variables in scope at it (`env[i]` is the type of `$i`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HoleInfo {
+    pub tp: SlowType,
+    pub env: VecDeque<SlowType>,
+}
+
+impl HoleInfo {
+    pub fn new(tp: SlowType, env: VecDeque<SlowType>) -> Self {
+        HoleInfo { tp, env }
+    }
+
+    /// instantiate the type and environment together, so type variables they share stay shared
+    pub fn instantiate(&self, ctx: &mut Context) -> HoleInfo {
+        let all = SlowType::Term("hole".into(), std::iter::once(&self.tp).chain(self.env.iter()).cloned().collect());
+        match all.instantiate(ctx) {
+            SlowType::Term(_, mut tps) => {
+                let env = tps.split_off(1).into();
+                HoleInfo::new(tps.pop().unwrap(), env)
+            },
+            SlowType::Var(_) => unreachable!(),
+        }
+    }
+
+    pub fn apply(&self, ctx: &Context) -> HoleInfo {
+        HoleInfo::new(self.tp.apply(ctx), self.env.iter().map(|tp| tp.apply(ctx)).collect())
+    }
+}
+
+impl ExprSet {
+    /// add a new Node::Hole with a fresh HoleId, recording `info` about it if it's known
+    pub fn add_hole(&mut self, info: Option<HoleInfo>) -> Idx {
+        let id = self.holes.len();
+        self.holes.push(info);
+        self.add(Node::Hole(id))
+    }
+
+    /// the recorded type and environment of a hole, if any
+    pub fn hole_info(&self, id: HoleId) -> Option<&HoleInfo> {
+        self.holes.get(id).and_then(|info| info.as_ref())
+    }
+
+    /// make sure the hole table has an entry for `id`, eg for holes that came from parsing `?3`
+    pub(crate) fn reserve_hole(&mut self, id: HoleId) {
+        if self.holes.len() <= id {
+            self.holes.resize(id + 1, None);
+        }
+    }
+}
+
+impl<'a> Expr<'a> {
+    /// the ids of the typed holes in this expression, left to right, each listed once
+    pub fn holes(&self) -> Vec<HoleId> {
+        let mut holes = vec![];
+        let mut worklist = vec![self.idx];
+        while let Some(i) = worklist.pop() {
+            if i == HOLE {
+                continue
+            }
+            if let Node::Hole(id) = self.get_node(i) {
+                if !holes.contains(id) {
+                    holes.push(*id);
+                }
+            }
+            // reversed so the left child is popped first
+            let children: Vec<Idx> = self.get(i).children().collect();
+            worklist.extend(children.into_iter().rev());
+        }
+        holes
+    }
+}
+
+impl<'a> ExprMut<'a> {
+    /// Fill every occurrence of the hole `id` in this expression with `replacement`, which should be written
+    /// relative to the hole's environment so it's not shifted. Returns the Idx of the filled expression. In
+    /// a ChildFirst set this rebuilds the path to each hole with .add() (so structural hashing is maintained
+    /// and the original is untouched), while in ParentFirst and Any sets a copy of `replacement` is added for each
+    /// hole with copy_span() and the hole node is overwritten in place with the copy's root (which can be undone
+    /// with load_state()), so the root Idx is unchanged.
+    pub fn fill_hole(&mut self, id: HoleId, replacement: Idx) -> Idx {
+        if self.set.order == Order::ChildFirst {
+            fn helper(e: &mut ExprMut, id: HoleId, replacement: Idx) -> Idx {
+                if e.idx == HOLE {
+                    return HOLE
+                }
+                match e.node().clone() {
+                    Node::Hole(i) if i == id => replacement,
+                    Node::Prim(_) | Node::Var(_) | Node::IVar(_) | Node::Hole(_) => e.idx,
+                    Node::App(f, x) => {
+                        let new_f = helper(&mut e.get(f), id, replacement);
+                        let new_x = helper(&mut e.get(x), id, replacement);
+                        if (new_f, new_x) == (f, x) { e.idx } else { e.set.add(Node::App(new_f, new_x)) }
+                    },
+                    Node::Lam(b) => {
+                        let new_b = helper(&mut e.get(b), id, replacement);
+                        if new_b == b { e.idx } else { e.set.add(Node::Lam(new_b)) }
+                    },
+                }
+            }
+            return helper(self, id, replacement)
+        }
+
+        let targets: Vec<Idx> = {
+            let mut targets = vec![];
+            let mut worklist = vec![self.idx];
+            while let Some(i) = worklist.pop() {
+                if i == HOLE {
+                    continue
+                }
+                if self.set[i] == Node::Hole(id) {
+                    targets.push(i);
+                }
+                worklist.extend(self.set.get(i).children());
+            }
+            targets
+        };
+        // copy_span() needs a ChildFirst source with spans, like in parse_extend()
+        fn copy_child_first(e: Expr, child_first: &mut ExprSet) -> Idx {
+            if e.idx == HOLE {
+                return HOLE
+            }
+            let node = match e.node() {
+                Node::Prim(_) | Node::Var(_) | Node::IVar(_) | Node::Hole(_) => e.node().clone(),
+                Node::App(f, x) => Node::App(copy_child_first(e.get(*f), child_first), copy_child_first(e.get(*x), child_first)),
+                Node::Lam(b) => Node::Lam(copy_child_first(e.get(*b), child_first)),
+            };
+            child_first.add(node)
+        }
+        let mut child_first = ExprSet::empty(Order::ChildFirst, true, false);
+        let replacement = copy_child_first(self.set.get(replacement), &mut child_first);
+        for i in targets {
+            let copy = child_first.get(replacement).copy_span(self.set);
+            let node = self.set[copy].clone();
+            let mut e = self.get(i);
+            e.log_edit();
+            *e.node() = node;
+            debug_assert!(e.immut().node_order_safe());
+        }
+        self.idx
+    }
+
+    /// Infer the type of this expression as `tp` in the environment `env`, recording the expected type and
+    /// environment of every typed hole in it into the set's hole table. Holes that already have recorded
+    /// info are checked against it (see infer_holes()) rather than overwritten, so this fails if the sketch
+    /// doesn't fit them. All types are relative to `ctx`.
+    pub fn type_holes<D: Domain>(&mut self, tp: &SlowType, env: &VecDeque<SlowType>, ctx: &mut Context, dsl: &DSL<D>) -> Result<(), UnifyErr> {
+        let mut found = vec![];
+        let inferred = self.immut().infer_holes(ctx, &mut env.clone(), dsl, &mut found)?;
+        ctx.unify(&inferred, tp)?;
+        for (id, info) in found {
+            if self.set.hole_info(id).is_some() {
+                continue
+            }
+            self.set.reserve_hole(id);
+            self.set.holes[id] = Some(info.apply(ctx));
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use domains::simple::SimpleVal;
+
+    #[test]
+    fn test_holes() {
+        let dsl = SimpleVal::new_dsl();
+        let int = SlowType::base("int".into());
+        for order in [Order::ChildFirst, Order::ParentFirst, Order::Any] {
+            let set = &mut ExprSet::empty(order.clone(), false, false);
+            let e = set.parse_extend("(lam (+ ?0 (map (lam ?1) ?2)))").unwrap();
+            assert_eq!(set.get(e).to_string(), "(lam (+ ?0 (map (lam ?1) ?2)))");
+            assert_eq!(set.get(e).holes(), vec![0, 1, 2]);
+            assert_eq!(set.hole_info(0), None);
+
+            // typing the sketch records what each hole expects
+            let mut ctx = Context::empty();
+            set.get_mut(e).type_holes(&"int -> int".parse().unwrap(), &Default::default(), &mut ctx, &dsl).unwrap_err();
+            let mut ctx = Context::empty();
+            let e = set.parse_extend("(lam (+ ?0 (sum (map (lam ?1) ?2))))").unwrap();
+            set.get_mut(e).type_holes(&"int -> int".parse().unwrap(), &Default::default(), &mut ctx, &dsl).unwrap();
+            assert_eq!(set.hole_info(0), Some(&HoleInfo::new(int.clone(), vec![int.clone()].into())));
+            let info1 = set.hole_info(1).unwrap().clone();
+            assert_eq!(info1.tp, int);
+            assert_eq!(info1.env.len(), 2);
+            assert_eq!(info1.env[1], int);
+            assert_eq!(set.hole_info(2).unwrap().tp, SlowType::Term("list".into(), vec![info1.env[0].clone()]));
+
+            // fill holes one at a time by id
+            let three = set.parse_extend("3").unwrap();
+            let e = set.get_mut(e).fill_hole(0, three);
+            assert_eq!(set.get(e).to_string(), "(lam (+ 3 (sum (map (lam ?1) ?2))))");
+            let body = set.parse_extend("(+ $0 $1)").unwrap();
+            let e = set.get_mut(e).fill_hole(1, body);
+            assert_eq!(set.get(e).holes(), vec![2]);
+            let list = set.parse_extend("[1,2]").unwrap();
+            let e = set.get_mut(e).fill_hole(2, list);
+            assert_eq!(set.get(e).to_string(), "(lam (+ 3 (sum (map (lam (+ $0 $1)) [1,2]))))");
+            assert!(set.get(e).holes().is_empty());
+            assert_eq!(set.get(e).infer(&mut Context::empty(), &mut Default::default(), &dsl), Ok("int -> int".parse().unwrap()));
+        }
+
+        // holes made during a top down search carry their types, and filling them rolls back with load_state()
+        let set = &mut ExprSet::empty(Order::ParentFirst, false, false);
+        let root = set.add_hole(Some(HoleInfo::new(int.clone(), Default::default())));
+        let state = set.save_state();
+        let app = set.add(Node::App(HOLE,HOLE));
+        let inc = set.parse_extend("(+ 1)").unwrap();
+        set.get_mut(app).expand(inc);
+        let arg = set.add_hole(Some(HoleInfo::new(int.clone(), Default::default())));
+        set.get_mut(app).expand(arg);
+        let e = set.get_mut(root).fill_hole(0, app);
+        assert_eq!(e, root);
+        assert_eq!(set.get(root).to_string(), "(+ 1 ?1)");
+        assert_eq!(set.hole_info(1).unwrap().tp, int);
+        set.load_state(state);
+        assert_eq!(set.get(root).to_string(), "?0");
+        assert_eq!(set.holes.len(), 1);
+
+        // every occurrence of a hole has the same type, which must fit any recorded info once instantiated
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, false);
+        let e = set.parse_extend("(+ ?0 (sum ?0))").unwrap();
+        assert!(set.get_mut(e).type_holes(&int, &Default::default(), &mut Context::empty(), &dsl).is_err());
+        let id = set.holes.len();
+        let hole = set.add_hole(Some(HoleInfo::new("t0 -> t0".parse().unwrap(), Default::default())));
+        let list = set.parse_extend("[1,2]").unwrap();
+        let map = set.parse_extend("map").unwrap();
+        let e = set.add(Node::App(map, hole));
+        let e = set.add(Node::App(e, list));
+        let mut ctx = Context::empty();
+        let t0 = ctx.fresh_type_var();
+        ctx.unify(&t0, &"list int".parse().unwrap()).unwrap();
+        set.get_mut(e).type_holes(&"list int".parse().unwrap(), &Default::default(), &mut ctx, &dsl).unwrap();
+        assert_eq!(set.hole_info(id).unwrap().tp, "t0 -> t0".parse().unwrap());
+        assert!(set.get_mut(e).type_holes(&"list (list int)".parse().unwrap(), &Default::default(), &mut ctx, &dsl).is_err());
+
+        // filling copies the whole replacement, even if it comes before the holes in a ParentFirst set
+        let set = &mut ExprSet::empty(Order::ParentFirst, false, false);
+        let replacement = set.parse_extend("(+ 1 (lam $0))").unwrap();
+        let e = set.parse_extend("(+ ?0 (+ ?0 ?1))").unwrap();
+        let state = set.save_state();
+        let e = set.get_mut(e).fill_hole(0, replacement);
+        assert_eq!(set.get(e).to_string(), "(+ (+ 1 (lam $0)) (+ (+ 1 (lam $0)) ?1))");
+        assert!(set.iter().all(|i| set.get(i).node_order_safe()));
+        set.load_state(state);
+        assert_eq!(set.get(e).to_string(), "(+ ?0 (+ ?0 ?1))");
+
+        // the table survives reordering and compacting
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, true);
+        let e = set.parse_extend("(+ ?0 ?0)").unwrap();
+        set.get_mut(e).type_holes(&int, &Default::default(), &mut Context::empty(), &dsl).unwrap();
+        let (set, remap) = set.reorder(Order::ParentFirst, false);
+        assert_eq!(set.hole_info(0).unwrap().tp, int);
+        let (set, remap2) = set.compact(&[remap[e]]);
+        assert_eq!(set.get(remap2[remap[e]].unwrap()).holes(), vec![0]);
+        assert_eq!(set.hole_info(0).unwrap().tp, int);
+    }
+}
@@ -4,12 +4,19 @@ mod macros;
 mod expr;
 mod dsl;
 mod eval;
+mod cek;
 mod parse_type;
 mod parse_expr;
+mod parse_error;
 mod types;
 mod util;
 mod analysis;
 mod zipper;
+mod reduce;
+mod holes;
+mod enumerate;
+mod grammar;
+mod sample;
 pub mod domains;
 mod slow_types;
 
@@ -23,7 +30,13 @@ pub use {
     util::*,
     parse_expr::*,
     parse_type::*,
+    parse_error::*,
     analysis::*,
     zipper::*,
+    reduce::*,
+    holes::*,
+    enumerate::*,
+    grammar::*,
+    sample::*,
     eval::Val::*,
 };
\ No newline at end of file
@@ -6,8 +6,10 @@ macro_rules! load_args {
     (   $args:expr,
         $($name:ident : $type:ty ),*
     ) => { 
+        let mut _i = 0;
         $(
-            let $name:$type = FromVal::from_val($args.pop_front())?;
+            let $name:$type = FromVal::from_val($args.pop_front()).map_err(|e: VError| e.at_arg(_i))?;
+            _i += 1;
         )*
     }
 }
@@ -21,7 +23,7 @@ macro_rules! load_arg {
         $i:expr
     ) => {{ 
         let val = match load_arg_lazy!($args, $i) {
-            Val::Thunk(idx,env) => &$handle.eval_child(*idx, &env)?,
+            Val::Thunk(idx,env,origin) => &$handle.eval_child_in(origin, *idx, &env)?,
             val => val
         };        
         FromVal::from_val(val)?
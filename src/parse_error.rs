@@ -0,0 +1,89 @@
+use std::fmt::{self, Display, Formatter};
+
+/// The different ways parsing an expression or a type can fail
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// an openparen that is never closed or a closeparen that was never opened
+    MismatchedParens,
+    /// nothing to parse
+    EmptyInput,
+    /// `lam` that isn't written like `(lam body)` or `(lam x body)`
+    BadLam,
+    /// a `$i` or `#i` where `i` isn't an integer
+    BadIndex,
+    /// a name that isn't bound by a lambda and isn't a primitive
+    UnboundVariable,
+    /// empty parens or an empty string where a type was expected
+    EmptyType,
+    /// a type variable applied to args like `(t0 int)`
+    AppliedTypeVar,
+    /// a type with args applied to more args like `((list int) int)`
+    AppliedTerm,
+    /// an arrow with nothing to the left of it
+    MissingArrowArg,
+}
+
+/// An error from parsing an expression or a type, pointing to the byte offset in the input where it occurred
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub offset: usize, // byte offset into `input`
+    pub expected: Option<String>,
+    pub found: Option<String>,
+    pub input: String,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, input: &str, offset: usize) -> Self {
+        ParseError { kind, offset, expected: None, found: None, input: input.to_string() }
+    }
+    pub fn expected(mut self, expected: &str) -> Self {
+        self.expected = Some(expected.to_string());
+        self
+    }
+    pub fn found(mut self, found: &str) -> Self {
+        self.found = Some(found.to_string());
+        self
+    }
+}
+
+impl Display for ParseErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::MismatchedParens => write!(f, "mismatched parens"),
+            ParseErrorKind::EmptyInput => write!(f, "input is empty"),
+            ParseErrorKind::BadLam => write!(f, "`lam` must be written with parens and a single body like `(lam (foo bar))`"),
+            ParseErrorKind::BadIndex => write!(f, "variables must be written like `$0` or `#0`"),
+            ParseErrorKind::UnboundVariable => write!(f, "unbound variable"),
+            ParseErrorKind::EmptyType => write!(f, "unexpected empty parens or empty string in type"),
+            ParseErrorKind::AppliedTypeVar => write!(f, "type variable is applied to args"),
+            ParseErrorKind::AppliedTerm => write!(f, "Term type applied to args like ((list int) int)"),
+            ParseErrorKind::MissingArrowArg => write!(f, "no args to the left of an arrow"),
+        }
+    }
+}
+
+/// Renders like:
+/// ```text
+/// parse error at byte 5: mismatched parens (expected `)`, found end of input)
+///   (foo (bar baz)
+///        ^
+/// ```
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "parse error at byte {}: {}", self.offset, self.kind)?;
+        match (&self.expected, &self.found) {
+            (Some(expected), Some(found)) => write!(f, " (expected {}, found {})", expected, found)?,
+            (Some(expected), None) => write!(f, " (expected {})", expected)?,
+            (None, Some(found)) => write!(f, " (found {})", found)?,
+            (None, None) => {},
+        }
+        // show the line of the input containing the offset with a caret under the offending char
+        let line_start = self.input[..self.offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = self.input[self.offset..].find('\n').map(|i| i + self.offset).unwrap_or(self.input.len());
+        let column = self.input[line_start..self.offset].chars().count();
+        write!(f, "\n  {}\n  {}^", &self.input[line_start..line_end], " ".repeat(column))
+    }
+}
+
+impl std::error::Error for ParseError {}
@@ -1,6 +1,6 @@
 use std::{fmt::{Display, Formatter, self}};
 
-use crate::expr::*;
+use crate::*;
 
 
 /// Takes a string, parses it into an Expr, then returns that string printed back out.
@@ -21,6 +21,7 @@ impl Display for Node {
             Self::App(_,_) => write!(f,"app"),
             Self::Lam(_) => write!(f,"lam"),
             Self::IVar(i) => write!(f,"#{}",i),
+            Self::Hole(i) => write!(f,"?{}",i),
         }
     }
 }
@@ -33,7 +34,7 @@ impl<'a> Display for Expr<'a> {
             }
 
             match e.node() {
-                Node::Var(_) | Node::IVar(_) | Node::Prim(_) => write!(f,"{}", e.node()),
+                Node::Var(_) | Node::IVar(_) | Node::Prim(_) | Node::Hole(_) => write!(f,"{}", e.node()),
                 Node::App(fun,x) => {
                     // if you are the left side of an application, and you are an application, you dont need parens
                     if !left_of_app { write!(f,"(")? }
@@ -59,143 +60,362 @@ impl Display for ExprOwned {
     }
 }
 
-impl ExprSet {
-    /// parses `s_init` as an Expr, inserting it into `self`. Uses .add() so spans
-    /// and structural hashing are done automatically. Is order-aware.
-    pub fn parse_extend(&mut self, s_init: &str) -> Result<Idx,String> {
-        let init_len = self.nodes.len();
-
-        let mut s = s_init.trim();
-
-        let mut items: Vec<Idx> = vec![];
-        let mut items_of_depth: Vec<usize> = vec![]; // offsets[i] gives the number of items at depth i
-        items_of_depth.push(0); // the zero paren depth
-
-        while !s.trim().is_empty() {
-            s = s.trim();
-            let next =  s.chars().last().unwrap();
-            if next == '(' {
-                s = &s[..s.len()-1];
-                let num_items = items_of_depth.pop().ok_or_else(||format!("ExprSet parse error: mismatched parens in: {}",s_init))?;
-                if num_items == 0 {
-                    continue
-                }
+/// Displays an Expr with named binders like `(lam x (lam y (+ x y)))` instead of de Bruijn
+/// indices. See Expr::named().
+#[derive(Clone, Copy, Debug)]
+pub struct NamedExpr<'a> {
+    pub expr: Expr<'a>,
+}
 
-                
-                // now num_items >= 1. The following loop will only happen if num_items >= 2.
-                // apply the last item to the second to last, etc
-                for _ in 0..num_items-1 {
-                    // println!("built an app inside");
-                    let f: Idx = items.pop().unwrap();
-                    let x: Idx = items.pop().unwrap();
-                    items.push(self.add(Node::App(f, x)))
-                }
-                // then we simply leave that final result pushed on
-                if let Some(num_items) = items_of_depth.last_mut() {
-                    *num_items += 1;
-                } else {
-                    return Err(format!("ExprSet parse error: mismatched parens in: {}",s_init));
-                }
+impl<'a> Expr<'a> {
+    /// view this Expr with named binders for printing. Each binder is named by its lambda depth
+    /// (x, y, z, w, u, v, x1, y1, ...) skipping any names used as primitives in the expression. Free
+    /// variables are left as `$i`. This can be parsed back in with parse_extend_named().
+    pub fn named(self) -> NamedExpr<'a> {
+        NamedExpr { expr: self }
+    }
+}
+
+impl<'a> Display for NamedExpr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // collect prims so we never generate a binder name that would shadow one
+        let mut prims: Vec<Symbol> = vec![];
+        let mut worklist: Vec<Expr> = vec![self.expr];
+        while let Some(e) = worklist.pop() {
+            if e.idx == HOLE {
                 continue
             }
-            if next == ')' {
-                s = &s[..s.len()-1];
-                items_of_depth.push(0);
-                continue
+            if let Node::Prim(p) = e.node() {
+                prims.push(p.clone());
             }
-            // parse a space-separated word
-            // println!("parsing with s: `{}`", s);
-            let start = {
-                let mut i = s.len()-1;
-                loop {
-                    if i == 0 {
-                        // println!("break at i==0");
-                        break
-                    }
-                    let c = s.chars().nth(i-1).unwrap();
-                    if c.is_whitespace() || c == '(' || c == ')' {
-                        // println!("break at c: {}", c);
-                        break
-                    }
-                    i -= 1;
-                }
-                // println!("i: {}", i);
-                i
-            };
-            let item_str = &s[start..];
-            // println!("item_str: {}", item_str);
-            s = &s[..start];
-
-            if item_str == "lam" || item_str == "lambda" {
-                // println!("remainder: {}",s);
-                let mut eof = false;
-                if let Some(c) = s.chars().last()  {
-                    if c != '(' {
-                        return Err(format!("ExprSet parse error: `lam` must always have an immediately preceding parenthesis like so `(lam` unless its at the start of the parsed string: {}",s_init))
+            worklist.extend(e.children().map(|c| e.get(c)));
+        }
+        let mut names: Vec<String> = vec![];
+        let mut fresh = (0..).map(|i: usize| {
+            let base = ["x","y","z","w","u","v"][i % 6];
+            if i < 6 { base.to_string() } else { format!("{}{}", base, i / 6) }
+        }).filter(|name| !prims.iter().any(|p| p.as_ref() == name.as_str()));
+
+        fn fmt_local(e: Expr, left_of_app: bool, binders: &mut Vec<String>, names: &mut Vec<String>, fresh: &mut dyn Iterator<Item=String>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            if e.idx == HOLE {
+                return write!(f,"??");
+            }
+
+            match e.node() {
+                Node::Var(i) => {
+                    if (*i as usize) < binders.len() {
+                        write!(f,"{}", binders[binders.len() - 1 - *i as usize])
+                    } else {
+                        write!(f,"{}", e.node())
                     }
-                    s = &s[..s.len()-1]; // strip "("
-                } else {
-                    eof = true;
-                };
-
-                let num_items = items_of_depth.pop().ok_or_else(||format!("ExprSet parse error: mismatched parens in: {}",s_init))?;
-                if num_items != 1 {
-                    return Err(format!("ExprSet parse error: `lam` must always be applied to exactly one argument, like `(lam (foo bar))`: {}",s_init))
-                }
-                let b: Idx = items.pop().unwrap();
-                items.push(self.add(Node::Lam(b)));
-                // println!("added lam");
-                if eof {
-                    if items.len() != 1 {
-                        return Err(format!("ExprSet parse error: mismatched parens in: {}",s_init));
+                },
+                Node::IVar(_) | Node::Prim(_) | Node::Hole(_) => write!(f,"{}", e.node()),
+                Node::App(fun,x) => {
+                    // if you are the left side of an application, and you are an application, you dont need parens
+                    if !left_of_app { write!(f,"(")? }
+                    fmt_local(e.get(*fun), true, binders, names, fresh, f)?;
+                    write!(f," ")?;
+                    fmt_local(e.get(*x), false, binders, names, fresh, f)?;
+                    if !left_of_app { write!(f,")") } else { Ok(()) }
+                },
+                Node::Lam(b) => {
+                    // binders at the same depth reuse the same name
+                    if names.len() <= binders.len() {
+                        names.push(fresh.next().unwrap());
                     }
-                    return Ok(items.pop().unwrap())
-                }
-                if let Some(num_items) = items_of_depth.last_mut() {
-                    *num_items += 1;
-                } else {
-                    return Err(format!("ExprSet parse error: mismatched parens in: {}",s_init));
+                    let name = names[binders.len()].clone();
+                    write!(f,"(lam {} ", name)?;
+                    binders.push(name);
+                    fmt_local(e.get(*b), false, binders, names, fresh, f)?;
+                    binders.pop();
+                    write!(f,")")
                 }
-                continue
             }
+        }
+        fmt_local(self.expr, false, &mut vec![], &mut names, &mut fresh, f)
+    }
+}
 
-            let node = {
-                if let Some(rest) = item_str.strip_prefix('$') {
-                    Node::Var(rest.parse::<i32>().map_err(|e|e.to_string())?)
-                } else if let Some(rest) = item_str.strip_prefix('#') {
-                    Node::IVar(rest.parse::<i32>().map_err(|e|e.to_string())?)
-                } else {
-                    Node::Prim(item_str.into())
-                }
-            };
-            items.push(self.add(node));
-            *items_of_depth.last_mut().unwrap() += 1;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Token<'s> {
+    Open,
+    Close,
+    Atom(&'s str),
+}
+
+/// Splits a string into parens and whitespace-separated atoms in a single forward pass,
+/// yielding each token along with its byte offset. Atoms may contain any non-whitespace
+/// unicode other than parens.
+struct Lexer<'s> {
+    s: &'s str,
+    pos: usize,
+}
+
+impl<'s> Lexer<'s> {
+    fn new(s: &'s str) -> Self {
+        Lexer { s, pos: 0 }
+    }
+}
+
+impl<'s> Iterator for Lexer<'s> {
+    type Item = (usize, Token<'s>);
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.s[self.pos..];
+        let start = self.pos + (rest.len() - rest.trim_start().len());
+        let rest = &self.s[start..];
+        let c = rest.chars().next()?;
+        let (len, token) = match c {
+            '(' => (1, Token::Open),
+            ')' => (1, Token::Close),
+            _ => {
+                let len = rest.find(|c: char| c.is_whitespace() || c == '(' || c == ')').unwrap_or(rest.len());
+                (len, Token::Atom(&rest[..len]))
+            }
+        };
+        self.pos = start + len;
+        Some((start, token))
+    }
+}
+
+impl ExprSet {
+    /// parses `s_init` written with named binders like `(lam x (lam y (+ x y)))`, converting bound names to
+    /// de Bruijn indices, and inserts it into `self` like parse_extend(). Any name that isn't bound must satisfy
+    /// `is_prim` (eg `|p| dsl.val_of_prim(&p.into()).is_some()`) or an unbound variable error is returned.
+    /// Unnamed `(lam body)` binders, `$i` (counted across all binders), `#i` and holes are still accepted.
+    pub fn parse_extend_named(&mut self, s_init: &str, is_prim: impl Fn(&str) -> bool) -> Result<Idx,ParseError> {
+        if self.order == Order::ParentFirst {
+            // build children before parents like parse_extend() does
+            assert!(self.struct_hash.is_none(), "struct_hash=true requires order=ChildFirst");
+            let mut child_first = ExprSet::empty(Order::ChildFirst, true, false);
+            let idx = child_first.parse_extend_named(s_init, is_prim)?;
+            if child_first.holes.len() > self.holes.len() {
+                self.reserve_hole(child_first.holes.len() - 1);
+            }
+            if idx == HOLE {
+                return Ok(HOLE)
+            }
+            return Ok(child_first.get(idx).copy_span(self));
         }
 
-        if items.is_empty() {
-            return Err("ExprSet parse error: input is empty string".to_string());
+        fn is_index(name: &str) -> bool {
+            name.starts_with('$') || name.starts_with('#')
+        }
+        fn is_lam(token: Option<&(usize, Token)>) -> bool {
+            matches!(token, Some((_, Token::Atom("lam" | "lambda"))))
         }
 
-        if items_of_depth.len() != 1 {
-            return Err(format!("ExprSet parse error: mismatched parens in: {}",s_init));
+        // a single forward pass like parse_extend(), except that each `lam` pushes its binder (None if unnamed) onto
+        // `binders` until its frame closes, and whether a lam is named needs a two token lookahead
+        let tokens: Vec<(usize, Token)> = Lexer::new(s_init).collect();
+        let first_offset = s_init.len() - s_init.trim_start().len();
+        let mut stack: Vec<Frame> = vec![Frame::new(None)];
+        let mut binders: Vec<Option<&str>> = vec![];
+        let bad_lam = |frame: &Frame, found: &str| ParseError::new(ParseErrorKind::BadLam, s_init, frame.open.unwrap_or(frame.lam.unwrap()))
+            .expected("an optional binder name and a body like `(lam x (foo x))`").found(found);
+        // add an item to the innermost frame, erroring right away if it's a lam with more than one body
+        let push = |stack: &mut Vec<Frame>, set: &mut ExprSet, idx: Idx| -> Result<(),ParseError> {
+            let frame = stack.last_mut().unwrap();
+            frame.push(set, idx);
+            if frame.lam.is_some() && frame.num_items > 1 {
+                return Err(bad_lam(frame, "more than one body"))
+            }
+            Ok(())
+        };
+        // finish a frame, popping its binder if it's a lam
+        let finish = |frame: Frame, binders: &mut Vec<Option<&str>>, set: &mut ExprSet| -> Result<Option<Idx>,ParseError> {
+            if frame.lam.is_none() {
+                return Ok(frame.acc)
+            }
+            binders.pop();
+            match frame.acc {
+                Some(body) => Ok(Some(set.add(Node::Lam(body)))),
+                None => Err(bad_lam(&frame, "no body")),
+            }
+        };
+
+        let mut i = 0;
+        while i < tokens.len() {
+            let (offset, token) = tokens[i];
+            i += 1;
+            match token {
+                Token::Open => stack.push(Frame::new(Some(offset))),
+                Token::Close => {
+                    if stack.len() == 1 {
+                        return Err(ParseError::new(ParseErrorKind::MismatchedParens, s_init, offset).found("`)` with no matching `(`"));
+                    }
+                    if let Some(idx) = finish(stack.pop().unwrap(), &mut binders, self)? {
+                        push(&mut stack, self, idx)?;
+                    }
+                },
+                Token::Atom("lam" | "lambda") => {
+                    let frame = stack.last_mut().unwrap();
+                    let starts_frame = match frame.open {
+                        Some(open) => open + 1 == offset && frame.num_items == 0,
+                        None => offset == first_offset,
+                    };
+                    if !starts_frame {
+                        return Err(ParseError::new(ParseErrorKind::BadLam, s_init, offset).expected("`(` right before `lam`"))
+                    }
+                    frame.lam = Some(offset);
+                    // `lam x body` is named, while in `lam x` the x is the body
+                    let binder = match (tokens.get(i), tokens.get(i + 1)) {
+                        (Some((_, Token::Atom(name))), Some((_, next))) if !is_index(name) && !is_lam(tokens.get(i)) && *next != Token::Close => Some(*name),
+                        _ => None,
+                    };
+                    if binder.is_some() {
+                        i += 1;
+                    }
+                    binders.push(binder);
+                },
+                Token::Atom(name) if name == HOLE_SYM.as_ref() => push(&mut stack, self, HOLE)?,
+                Token::Atom(name) => {
+                    let bad_index = || ParseError::new(ParseErrorKind::BadIndex, s_init, offset).found(name);
+                    let node = if let Some(rest) = name.strip_prefix('$') {
+                        Node::Var(rest.parse::<i32>().map_err(|_| bad_index())?)
+                    } else if let Some(rest) = name.strip_prefix('#') {
+                        Node::IVar(rest.parse::<i32>().map_err(|_| bad_index())?)
+                    } else if let Some(id) = name.strip_prefix('?').and_then(|rest| rest.parse::<HoleId>().ok()) {
+                        self.reserve_hole(id);
+                        Node::Hole(id)
+                    } else if let Some(i) = binders.iter().rev().position(|binder| *binder == Some(name)) {
+                        Node::Var(i as i32)
+                    } else if is_prim(name) {
+                        Node::Prim(name.into())
+                    } else {
+                        return Err(ParseError::new(ParseErrorKind::UnboundVariable, s_init, offset).found(name))
+                    };
+                    let idx = self.add(node);
+                    push(&mut stack, self, idx)?;
+                },
+            }
         }
 
-        let num_items = items_of_depth.pop().unwrap();
-        // println!("items outside: {}", num_items);
-        for _ in 0..num_items-1 {
-            // println!("built an app outside");
-            let f: Idx = items.pop().unwrap();
-            let x: Idx = items.pop().unwrap();
-            items.push(self.add(Node::App(f, x)))
+        if stack.len() > 1 {
+            let offset = stack.last().unwrap().open.unwrap();
+            return Err(ParseError::new(ParseErrorKind::MismatchedParens, s_init, offset).expected("`)`").found("end of input"));
         }
-        if items.len() != 1 {
-            return Err(format!("ExprSet parse error: mismatched parens in: {}",s_init));
+        finish(stack.pop().unwrap(), &mut binders, self)?
+            .ok_or_else(|| ParseError::new(ParseErrorKind::EmptyInput, s_init, 0).expected("an expression"))
+    }
+}
+
+/// One level of paren nesting while parsing. Items are applied to each other as soon as
+/// they're parsed so `(f x y)` becomes `((f x) y)` without buffering.
+struct Frame {
+    open: Option<usize>, // offset of the openparen, or None at the top level
+    lam: Option<usize>, // offset of the `lam` token if this frame is a lambda
+    acc: Option<Idx>, // all items so far applied together
+    num_items: usize,
+}
+
+impl Frame {
+    fn new(open: Option<usize>) -> Self {
+        Frame { open, lam: None, acc: None, num_items: 0 }
+    }
+
+    fn push(&mut self, set: &mut ExprSet, item: Idx) {
+        self.acc = Some(match self.acc {
+            None => item,
+            Some(f) => set.add(Node::App(f, item)),
+        });
+        self.num_items += 1;
+    }
+
+    /// the finished expression of this frame, or None for empty parens
+    fn finish(self, set: &mut ExprSet, s_init: &str) -> Result<Option<Idx>,ParseError> {
+        match self.lam {
+            // `lam` must always be applied to exactly one argument, like `(lam (foo bar))`
+            Some(offset) if self.num_items != 1 => Err(ParseError::new(ParseErrorKind::BadLam, s_init, offset).expected("one body").found(&format!("{} items", self.num_items))),
+            Some(_) => Ok(Some(set.add(Node::Lam(self.acc.unwrap())))),
+            None => Ok(self.acc),
         }
+    }
+}
 
+impl ExprSet {
+    /// parses `s_init` as an Expr, inserting it into `self`. Uses .add() so spans
+    /// and structural hashing are done automatically. Is order-aware. Runs in a single
+    /// forward pass over the input.
+    ///
+    /// `??` parses to a HOLE child (and a lone `??` returns HOLE itself), so partial programs printed
+    /// by Display round trip. Holes can be filled with ExprMut::expand() only in ParentFirst or Any sets, since
+    /// filling them in a ChildFirst set would point a parent at a later child. ChildFirst sets can still hold and
+    /// print sketches, and reorder() converts them for expanding.
+    ///
+    /// `?i` parses to the typed hole Node::Hole(i), see ExprMut::type_holes() and ExprMut::fill_hole().
+    pub fn parse_extend(&mut self, s_init: &str) -> Result<Idx,ParseError> {
         if self.order == Order::ParentFirst {
-            self.nodes[init_len..].reverse();
+            // the parser builds children before parents, so build into a ChildFirst set and
+            // copy it over, which reverses the nodes and remaps their children and spans
+            assert!(self.struct_hash.is_none(), "struct_hash=true requires order=ChildFirst");
+            let mut child_first = ExprSet::empty(Order::ChildFirst, true, false);
+            let idx = child_first.parse_extend(s_init)?;
+            if child_first.holes.len() > self.holes.len() {
+                self.reserve_hole(child_first.holes.len() - 1);
+            }
+            if idx == HOLE {
+                return Ok(HOLE)
+            }
+            return Ok(child_first.get(idx).copy_span(self));
+        }
+
+        // offset of the first token, where a top level `lam b` is allowed
+        let first_offset = s_init.len() - s_init.trim_start().len();
+        let mut stack: Vec<Frame> = vec![Frame::new(None)];
+
+        for (offset, token) in Lexer::new(s_init) {
+            match token {
+                Token::Open => stack.push(Frame::new(Some(offset))),
+                Token::Close => {
+                    if stack.len() == 1 {
+                        return Err(ParseError::new(ParseErrorKind::MismatchedParens, s_init, offset).found("`)` with no matching `(`"));
+                    }
+                    if let Some(idx) = stack.pop().unwrap().finish(self, s_init)? {
+                        stack.last_mut().unwrap().push(self, idx);
+                    }
+                },
+                Token::Atom(item_str) if item_str == "lam" || item_str == "lambda" => {
+                    let frame = stack.last_mut().unwrap();
+                    // `lam` must always have an immediately preceding parenthesis like so `(lam` unless its at the start of the parsed string
+                    let starts_frame = match frame.open {
+                        Some(open) => open + 1 == offset && frame.num_items == 0,
+                        None => offset == first_offset,
+                    };
+                    if !starts_frame {
+                        return Err(ParseError::new(ParseErrorKind::BadLam, s_init, offset).expected("`(` right before `lam`"))
+                    }
+                    frame.lam = Some(offset);
+                },
+                Token::Atom(item_str) if item_str == HOLE_SYM.as_ref() => {
+                    stack.last_mut().unwrap().push(self, HOLE);
+                },
+                Token::Atom(item_str) => {
+                    let bad_index = || ParseError::new(ParseErrorKind::BadIndex, s_init, offset).found(item_str);
+                    let node = {
+                        if let Some(rest) = item_str.strip_prefix('$') {
+                            Node::Var(rest.parse::<i32>().map_err(|_| bad_index())?)
+                        } else if let Some(rest) = item_str.strip_prefix('#') {
+                            Node::IVar(rest.parse::<i32>().map_err(|_| bad_index())?)
+                        } else if let Some(id) = item_str.strip_prefix('?').and_then(|rest| rest.parse::<HoleId>().ok()) {
+                            self.reserve_hole(id);
+                            Node::Hole(id)
+                        } else {
+                            Node::Prim(item_str.into())
+                        }
+                    };
+                    let idx = self.add(node);
+                    stack.last_mut().unwrap().push(self, idx);
+                },
+            }
+        }
+
+        if stack.len() > 1 {
+            let offset = stack.last().unwrap().open.unwrap();
+            return Err(ParseError::new(ParseErrorKind::MismatchedParens, s_init, offset).expected("`)`").found("end of input"));
         }
-        Ok(items.pop().unwrap())
+
+        stack.pop().unwrap().finish(self, s_init)?
+            .ok_or_else(|| ParseError::new(ParseErrorKind::EmptyInput, s_init, 0).expected("an expression"))
     }
 }
 
@@ -236,7 +456,250 @@ mod tests {
         let e = set.parse_extend("#3").unwrap();
         assert_eq!(set.get(e).node(), &Node::IVar(3));
 
-        assert_parse(set, "(fix1 $0 (lam (lam (if (empty? $0) $0 (cons (+ 1 (car $0)) ($1 (cdr $0)))))))", "(fix1 $0 (lam (lam (if (empty? $0) $0 (cons (+ 1 (car $0)) ($1 (cdr $0)))))))")
+        assert_parse(set, "(fix1 $0 (lam (lam (if (empty? $0) $0 (cons (+ 1 (car $0)) ($1 (cdr $0)))))))", "(fix1 $0 (lam (lam (if (empty? $0) $0 (cons (+ 1 (car $0)) ($1 (cdr $0)))))))");
+
+        // whitespace, empty parens, and multi-byte symbols
+        assert_parse(set, "  (foo\n\t(bar  baz) )  ", "(foo (bar baz))");
+        assert_parse(set, "(foo () bar)", "(foo bar)");
+        assert_parse(set, "(∘ f→g (λx ü))", "(∘ f→g (λx ü))");
+        assert_parse(set, "(lam(+ $0 ½))", "(lam (+ $0 ½))");
+
+        // struct hashing dedups across and within parses
+        let set = &mut ExprSet::empty(Order::ChildFirst, true, true);
+        let e1 = set.parse_extend("(+ (f x) (f x))").unwrap();
+        assert_eq!(set.len(), 6);
+        let e2 = set.parse_extend("((+ (f x)) (f x))").unwrap();
+        assert_eq!(e1, e2);
+        assert_eq!(set.len(), 6);
+
+        // long and deeply nested inputs
+        let n = 100_000;
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, false);
+        let e = set.parse_extend(&format!("(f{})", " x".repeat(n))).unwrap();
+        assert_eq!(set.len(), 2 * n + 1);
+        assert_eq!(set.get(e).node(), &Node::App(set.len() - 3, set.len() - 2));
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, false);
+        set.parse_extend(&format!("{}x{}", "(lam ".repeat(n), ")".repeat(n))).unwrap();
+        assert_eq!(set.len(), n + 1);
+    }
+
+    fn assert_parse_err(in_s: &str, kind: ParseErrorKind, offset: usize) {
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, false);
+        let err = set.parse_extend(in_s).unwrap_err();
+        assert_eq!((err.kind, err.offset), (kind, offset), "{}", in_s);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_parse_err("", ParseErrorKind::EmptyInput, 0);
+        assert_parse_err("  ", ParseErrorKind::EmptyInput, 0);
+        assert_parse_err("(foo (bar baz)", ParseErrorKind::MismatchedParens, 0);
+        assert_parse_err("(foo bar) (baz", ParseErrorKind::MismatchedParens, 10);
+        assert_parse_err("foo (", ParseErrorKind::MismatchedParens, 4);
+        assert_parse_err("(foo bar))", ParseErrorKind::MismatchedParens, 9);
+        assert_parse_err("foo) bar", ParseErrorKind::MismatchedParens, 3);
+        assert_parse_err("lam b)", ParseErrorKind::MismatchedParens, 5);
+        assert_parse_err("(foo lam b)", ParseErrorKind::BadLam, 5);
+        assert_parse_err("(foo (lam a b))", ParseErrorKind::BadLam, 6);
+        assert_parse_err("(foo (lam))", ParseErrorKind::BadLam, 6);
+        assert_parse_err("(lam b", ParseErrorKind::MismatchedParens, 0);
+        assert_parse_err("(+ $x 1)", ParseErrorKind::BadIndex, 3);
+        assert_parse_err("(+ #0 #)", ParseErrorKind::BadIndex, 6);
+
+        // rendering puts a caret under the error
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, false);
+        let err = set.parse_extend("(foo $x)").unwrap_err();
+        assert_eq!(err.to_string(), "parse error at byte 5: variables must be written like `$0` or `#0` (found $x)\n  (foo $x)\n       ^");
+        let err = set.parse_extend("(foo\n  (bar baz)))").unwrap_err();
+        assert_eq!(err.to_string(), "parse error at byte 17: mismatched parens (found `)` with no matching `(`)\n    (bar baz)))\n              ^");
+
+        // types
+        let err = "(list int".parse::<SlowType>().unwrap_err();
+        assert_eq!((err.kind, err.offset), (ParseErrorKind::MismatchedParens, 9));
+        let err = "list int)".parse::<SlowType>().unwrap_err();
+        assert_eq!((err.kind, err.offset), (ParseErrorKind::MismatchedParens, 8));
+        let err = "int -> ()".parse::<SlowType>().unwrap_err();
+        assert_eq!((err.kind, err.offset), (ParseErrorKind::EmptyType, 8));
+        let err = "-> int".parse::<SlowType>().unwrap_err();
+        assert_eq!((err.kind, err.offset), (ParseErrorKind::MissingArrowArg, 0));
+        let err = "int -> (t0 int)".parse::<SlowType>().unwrap_err();
+        assert_eq!((err.kind, err.offset), (ParseErrorKind::AppliedTypeVar, 8));
+        assert_eq!("int ->".parse::<SlowType>().unwrap_err().kind, ParseErrorKind::EmptyType);
+    }
+
+    #[test]
+    fn test_parse_named() {
+        let prims = ["+", "foo", "bar", "x1", "1", "2"];
+        let is_prim = |p: &str| prims.contains(&p);
+        fn assert_named(set: &mut ExprSet, is_prim: &dyn Fn(&str) -> bool, in_s: &str, debruijn: &str, named: &str) {
+            let e = set.parse_extend_named(in_s, is_prim).unwrap();
+            assert_eq!(set.get(e).to_string(), debruijn.to_string());
+            assert_eq!(set.get(e).named().to_string(), named.to_string());
+            // round trip
+            let e2 = set.parse_extend_named(named, is_prim).unwrap();
+            assert_eq!(set.get(e2).to_string(), debruijn.to_string());
+        }
+
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, false);
+        assert_named(set, &is_prim, "(+ 1 2)", "(+ 1 2)", "(+ 1 2)");
+        assert_named(set, &is_prim, "(lam a a)", "(lam $0)", "(lam x x)");
+        assert_named(set, &is_prim, "lam a (foo a)", "(lam (foo $0))", "(lam x (foo x))");
+        assert_named(set, &is_prim, "(lam a (lam b (+ a b)))", "(lam (lam (+ $1 $0)))", "(lam x (lam y (+ x y)))");
+        assert_named(set, &is_prim, "(lambda a (lambda b (+ a b)))", "(lam (lam (+ $1 $0)))", "(lam x (lam y (+ x y)))");
+        // shadowing
+        assert_named(set, &is_prim, "(lam a (lam a a))", "(lam (lam $0))", "(lam x (lam y y))");
+        // binders can shadow prims
+        assert_named(set, &is_prim, "(lam foo (foo bar))", "(lam ($0 bar))", "(lam x (x bar))");
+        // mixing with unnamed lambdas and de bruijn indices
+        assert_named(set, &is_prim, "(lam a (lam (+ a $0)))", "(lam (lam (+ $1 $0)))", "(lam x (lam y (+ x y)))");
+        // free variables are left alone
+        assert_named(set, &is_prim, "(lam a (+ a $1))", "(lam (+ $0 $1))", "(lam x (+ x $1))");
+        // sibling lambdas reuse names, and names that clash with prims are skipped
+        assert_named(set, &is_prim, "(foo (lam a a) (lam b (lam c (lam d (lam e (lam f (lam g (x1 g))))))))",
+            "(foo (lam $0) (lam (lam (lam (lam (lam (lam (x1 $0))))))))",
+            "(foo (lam x x) (lam x (lam y (lam z (lam w (lam u (lam v (x1 v))))))))");
+        assert_named(set, &is_prim, "(lam a (lam b (lam c (lam d (lam e (lam f (lam g (lam h (x1 h)))))))))",
+            "(lam (lam (lam (lam (lam (lam (lam (lam (x1 $0)))))))))",
+            "(lam x (lam y (lam z (lam w (lam u (lam v (lam y1 (lam z1 (x1 z1)))))))))");
+
+        // errors
+        let err = set.parse_extend_named("(lam a b)", is_prim).unwrap_err();
+        assert_eq!((err.kind, err.offset, err.found.as_deref()), (ParseErrorKind::UnboundVariable, 7, Some("b")));
+        let err = set.parse_extend_named("(+ a 1)", is_prim).unwrap_err();
+        assert_eq!((err.kind, err.offset, err.found.as_deref()), (ParseErrorKind::UnboundVariable, 3, Some("a")));
+        let err = set.parse_extend_named("(foo (lam a (lam b b) a b))", is_prim).unwrap_err();
+        assert_eq!((err.kind, err.offset), (ParseErrorKind::BadLam, 5));
+        let err = set.parse_extend_named("(lam a (foo a)", is_prim).unwrap_err();
+        assert_eq!((err.kind, err.offset), (ParseErrorKind::MismatchedParens, 0));
+        let err = set.parse_extend_named("(lam a $b)", is_prim).unwrap_err();
+        assert_eq!((err.kind, err.offset), (ParseErrorKind::BadIndex, 7));
+        assert_eq!(set.parse_extend_named(" ", is_prim).unwrap_err().kind, ParseErrorKind::EmptyInput);
+        // errors point at where they happened, however deep
+        let err = set.parse_extend_named("(lam a (foo a (lam)))", is_prim).unwrap_err();
+        assert_eq!((err.kind, err.offset), (ParseErrorKind::BadLam, 14));
+        let err = set.parse_extend_named("(lam a (lam b ()))", is_prim).unwrap_err();
+        assert_eq!((err.kind, err.offset), (ParseErrorKind::BadLam, 7));
+        assert_eq!(set.parse_extend_named("(foo () bar)", is_prim).map(|e| set.get(e).to_string()), Ok("(foo bar)".to_string()));
+
+        // long and deeply nested inputs
+        let n = 100_000;
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, false);
+        let e = set.parse_extend_named(&format!("(lam a (foo{}))", " a".repeat(n)), is_prim).unwrap();
+        assert_eq!(set.len(), 2 * n + 2);
+        assert_eq!(set.get(e).node(), &Node::Lam(set.len() - 2));
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, false);
+        let e = set.parse_extend_named(&format!("{}a{}", "(lam a ".repeat(n), ")".repeat(n)), is_prim).unwrap();
+        assert_eq!(set.len(), n + 1);
+        assert_eq!(set.get(set.len() - n - 1).node(), &Node::Var(0));
+        assert_eq!(set.get(e).node(), &Node::Lam(set.len() - 2));
+
+        // in every order, building the nodes directly
+        for order in [Order::ParentFirst, Order::Any] {
+            let set = &mut ExprSet::empty(order, true, false);
+            set.parse_extend("(foo bar)").unwrap();
+            let e = set.parse_extend_named("(lam a (foo (lam b (+ a b)) ?1 ??))", is_prim).unwrap();
+            assert_eq!(set.get(e).to_string(), "(lam (foo (lam (+ $1 $0)) ?1 ??))");
+            assert!(set.iter().all(|i| set.get(i).node_order_safe()));
+            assert_eq!(set.holes.len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_parse_holes() {
+        // holes parse to real HOLE children in every order
+        for order in [Order::ChildFirst, Order::ParentFirst, Order::Any] {
+            let set = &mut ExprSet::empty(order.clone(), true, false);
+            for prog in ["??", "(+ ?? (lam ??))", "(?? foo)", "(lam (?? $0 ??))"] {
+                let e = set.parse_extend(prog).unwrap();
+                assert_eq!(set.get(e).to_string(), prog);
+                assert!(set.iter().all(|i| set.get(i).node_order_safe()));
+            }
+            let e = set.parse_extend("(foo ??)").unwrap();
+            assert_eq!(set.get(e).right().idx, HOLE);
+            assert!(!set.iter().any(|i| matches!(set.get(i).node(), Node::Prim(p) if p == &*HOLE_SYM)));
+        }
+
+        // sketches in a ChildFirst set can be analyzed and reduced
+        let set = &mut ExprSet::empty(Order::ChildFirst, false, false);
+        let e = set.parse_extend("(+ ?? (lam ($1 ??)))").unwrap();
+        assert_eq!(*AnalyzedExpr::new(ExprCost::num_nodes()).analyze_get(set.get(e)), 6);
+        assert_eq!(*AnalyzedExpr::new(DepthAnalysis).analyze_get(set.get(e)), 4);
+        let mut fv = AnalyzedExpr::new(FreeVarAnalysis);
+        assert_eq!(fv.analyze_get(set.get(e)), &vec![0].into_iter().collect());
+        let e = set.parse_extend("((lam (lam ($1 ??))) (foo $0))").unwrap();
+        let res = set.get_mut(e).beta_normal(10, &mut fv);
+        assert!(res.is_normal());
+        assert_eq!(set.get(res.idx()).to_string(), "(lam (foo $1 ??))");
+        let e = set.parse_extend("((lam ($0 ??)) (lam $0))").unwrap();
+        assert_eq!(set.get_mut(e).beta_normal(10, &mut fv), Reduced::Normal(HOLE));
+
+        // a sketch grown top down can be printed, reloaded, and expanded further
+        let set = &mut ExprSet::empty(Order::ParentFirst, false, false);
+        let app1 = set.add(Node::App(HOLE,HOLE));
+        let app2 = set.add(Node::App(HOLE,HOLE));
+        let plus = set.add(Node::Prim("+".into()));
+        let lam = set.add(Node::Lam(HOLE));
+        set.get_mut(app1).expand(app2);
+        set.get_mut(app2).expand(plus);
+        set.get_mut(app1).expand(lam);
+        let sketch = set.get(app1).to_string();
+        assert_eq!(sketch, "(+ ?? (lam ??))");
+
+        let set = &mut ExprSet::empty(Order::ParentFirst, false, false);
+        let e = set.parse_extend(&sketch).unwrap();
+        let (app2, lam) = (set.get(e).left().idx, set.get(e).right().idx);
+        let two = set.add(Node::Prim("2".into()));
+        set.get_mut(app2).expand(two);
+        let three = set.add(Node::Prim("3".into()));
+        set.get_mut(lam).expand(three);
+        assert_eq!(set.get(e).to_string(), "(+ 2 (lam 3))");
+
+        // named binders too
+        let set = &mut ExprSet::empty(Order::ParentFirst, false, false);
+        let e = set.parse_extend_named("(lam x (foo x ?? ?0))", |p| p == "foo").unwrap();
+        assert_eq!(set.get(e).to_string(), "(lam (foo $0 ?? ?0))");
+    }
+
+    #[test]
+    fn test_parse_orders() {
+        let progs = [
+            "3",
+            "(+ 2 3)",
+            "(foo (bar baz))",
+            "(lam (+ $0 b))",
+            "(foo (lam b) (lam c))",
+            "(fix1 $0 (lam (lam (if (empty? $0) $0 (cons (+ 1 (car $0)) ($1 (cdr $0)))))))",
+        ];
+        for order in [Order::ChildFirst, Order::ParentFirst, Order::Any] {
+            for spans in [false, true] {
+                let set = &mut ExprSet::empty(order.clone(), spans, false);
+                // parse into a nonempty set so indices must be offset correctly
+                set.parse_extend("(foo bar)").unwrap();
+                for prog in progs {
+                    let e = set.parse_extend(prog).unwrap();
+                    assert_eq!(set.get(e).to_string(), prog.to_string());
+                    assert!(set.iter().all(|i| set.get(i).node_order_safe()));
+                    if spans {
+                        // cost_span() checks the span covers exactly the nodes of the expression
+                        let span = set.get(e).get_span().unwrap();
+                        assert_eq!(span.len() as i32, set.get(e).cost_span(&ExprCost::num_nodes()));
+                        if order == Order::ParentFirst {
+                            assert_eq!(span.start, e);
+                        }
+                    }
+                }
+            }
+        }
 
+        // copying between orders
+        let child_first = &mut ExprSet::empty(Order::ChildFirst, true, false);
+        let parent_first = &mut ExprSet::empty(Order::ParentFirst, true, false);
+        let prog = "(foo (lam (bar $0)) baz)";
+        let e = child_first.parse_extend(prog).unwrap();
+        let e = child_first.get(e).copy_span(parent_first);
+        assert_eq!(parent_first.get(e).to_string(), prog.to_string());
+        let e = parent_first.get(e).copy_span(child_first);
+        assert_eq!(child_first.get(e).to_string(), prog.to_string());
+        assert!(child_first.iter().all(|i| child_first.get(i).node_order_safe()));
     }
 }
\ No newline at end of file
@@ -1,39 +1,46 @@
-
 // use std::path::Path;
 
 use crate::*;
 
 
-/// this gets used by 
-pub fn parse(s: &str) -> Result<SlowType, String> {
-    let (ty, s_left) = parse_aux(s).map_err(|e| format!("{}\n when parsing: {}", e, s))?;
-        if !s_left.is_empty() {
-            return Err(format!("Type parse() error: extra closeparen\n when parsing: {}",s))
-        }
+/// this gets used by
+pub fn parse(s: &str) -> Result<SlowType, ParseError> {
+    let (ty, s_left) = parse_aux(s, s, false)?;
+    if !s_left.is_empty() {
+        return Err(ParseError::new(ParseErrorKind::MismatchedParens, s, offset_in(s, s_left)).found("`)` with no matching `(`"))
+    }
     Ok(ty)
 }
 
+/// byte offset of the substring `sub` within `s`
+fn offset_in(s: &str, sub: &str) -> usize {
+    sub.as_ptr() as usize - s.as_ptr() as usize
+}
+
 /// parses `s` into a type until hitting either a closeparen that hasn't been opened
 /// in `s`, or an end of string, and returns the type and the remaining string not including
-/// the closeparen if there was one
-fn parse_aux(mut s: &str) -> Result<(SlowType, &str), String> {
+/// the closeparen if there was one. `input` is the whole string being parsed, used for errors, and
+/// `nested` is true if we're inside parens that must be closed.
+fn parse_aux<'s>(input: &'s str, mut s: &'s str, nested: bool) -> Result<(SlowType, &'s str), ParseError> {
     let arrow = ARROW_SYM.as_ref();
     let mut res = vec![];
+    // offset of the start of this group, for errors about the group as a whole
+    let group_offset = offset_in(input, s);
 
-    fn finish(mut res: Vec<SlowType>) -> Result<SlowType, String> {
+    fn finish(mut res: Vec<SlowType>, input: &str, offset: usize) -> Result<SlowType, ParseError> {
         if res.is_empty() {
-            return Err("Type parse() error: unexpected empty parens or empty string in type".into())
+            return Err(ParseError::new(ParseErrorKind::EmptyType, input, offset).expected("a type"))
         }
         if res.len() == 1 {
             // we automatically strip down ((int)) -> int, and likewise "foo -> bar" is the same as "(foo -> bar)" at the top level
-            return Ok(res.pop().unwrap()) 
+            return Ok(res.pop().unwrap())
         }
         let head = res.remove(0);
         match head {
-            SlowType::Var(_) => Err("Type parse() error: type variable is applied to args".into()),
+            SlowType::Var(_) => Err(ParseError::new(ParseErrorKind::AppliedTypeVar, input, offset).found(&head.to_string())),
             SlowType::Term(name, args) => {
                 if !args.is_empty() {
-                    Err("Type parse() error: Term type applied to args like ((list int) int)".into())
+                    Err(ParseError::new(ParseErrorKind::AppliedTerm, input, offset))
                 } else {
                     Ok(SlowType::Term(name, res))
                 }
@@ -46,15 +53,19 @@ fn parse_aux(mut s: &str) -> Result<(SlowType, &str), String> {
 
         if s.is_empty() || s.starts_with(')') {
             // s is empty or hit closeparen: return
-            if !s.is_empty() {
+            if s.is_empty() && nested {
+                return Err(ParseError::new(ParseErrorKind::MismatchedParens, input, input.len()).expected("`)`").found("end of input"))
+            }
+            // only consume the closeparen if it closes our group, otherwise leave it for parse() to complain about
+            if nested {
                 s = &s[1..];
             }
-            return finish(res).map(|res| (res, s))
+            return finish(res, input, group_offset).map(|res| (res, s))
         }
 
-        if s.starts_with('(') {
+        if let Some(rest) = s.strip_prefix('(') {
             // hit an openparen: recurse
-            let (ty, s_new) = parse_aux(&s[1..])?;
+            let (ty, s_new) = parse_aux(input, rest, true)?;
             s = s_new;
             res.push(ty);
             continue
@@ -62,8 +73,8 @@ fn parse_aux(mut s: &str) -> Result<(SlowType, &str), String> {
 
         // no closeparen/openparen so must be a new token. Parse forward until hitting a space or end-of-string
 
-
-        let (item, s_new) = s.split_at(s.find(|c| c == ' ' || c == ')').unwrap_or(s.len()));
+        let item_offset = offset_in(input, s);
+        let (item, s_new) = s.split_at(s.find([' ', ')']).unwrap_or(s.len()));
         s = s_new;
 
         // check if it's a var like t0 t23 etc
@@ -77,18 +88,18 @@ fn parse_aux(mut s: &str) -> Result<(SlowType, &str), String> {
         // check if it's an arrow type and if so parse the left and right sides
         if item == arrow {
             if res.is_empty() {
-                return Err("Type parse() error: no args to the left of an arrow".into())
+                return Err(ParseError::new(ParseErrorKind::MissingArrowArg, input, item_offset).expected("a type").found(arrow))
             }
 
             // arrows are a low prio operator so group everything before into one term
-            let ty_left = finish(res).map_err(|s| format!("during arrow rearranging: {}",s))?;
+            let ty_left = finish(res, input, group_offset)?;
             // parse everything to the right
-            let (ty_right, s_new) = parse_aux(&s[1..])?;
+            let (ty_right, s_new) = parse_aux(input, s, nested)?;
             s = s_new;
             // construct the arrow
             return Ok((SlowType::Term(ARROW_SYM.clone(), vec![ty_left, ty_right]),s));
         }
-        
+
         // parse it as a new atomic type
         res.push(SlowType::Term(item.into(), vec![]))
 
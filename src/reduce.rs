@@ -0,0 +1,221 @@
+use crate::*;
+
+/// The outcome of reducing an expression under a step budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reduced {
+    /// the requested normal form was reached
+    Normal(Idx),
+    /// the budget ran out first; holds the partially reduced expression
+    OutOfFuel(Idx),
+}
+
+impl Reduced {
+    /// the resulting Idx regardless of whether a normal form was reached
+    pub fn idx(&self) -> Idx {
+        match self {
+            Reduced::Normal(idx) | Reduced::OutOfFuel(idx) => *idx,
+        }
+    }
+    pub fn is_normal(&self) -> bool {
+        matches!(self, Reduced::Normal(_))
+    }
+}
+
+impl<'a> ExprMut<'a> {
+    /// Perform a single leftmost-outermost (normal order) beta reduction step, returning
+    /// the Idx of the reduced expression or None if this is already in beta normal form.
+    /// All new nodes are added with .add() so spans and structural hashing are maintained.
+    /// Requires a ChildFirst set. Holes (including `??` children) are left as they are.
+    pub fn beta_step(&mut self, analyzed_free_vars: &mut AnalyzedExpr<FreeVarAnalysis>) -> Option<Idx> {
+        assert_eq!(self.set.order, Order::ChildFirst);
+        if self.idx == HOLE {
+            return None
+        }
+        match self.node().clone() {
+            Node::Prim(_) | Node::Var(_) | Node::IVar(_) | Node::Hole(_) => None,
+            Node::App(f, x) => {
+                if let Some(b) = lam_body(self.set, f) {
+                    return Some(beta_redex(self.set, b, x, analyzed_free_vars));
+                }
+                if let Some(f) = self.get(f).beta_step(analyzed_free_vars) {
+                    return Some(self.set.add(Node::App(f, x)));
+                }
+                self.get(x).beta_step(analyzed_free_vars).map(|x| self.set.add(Node::App(f, x)))
+            },
+            Node::Lam(b) => {
+                self.get(b).beta_step(analyzed_free_vars).map(|b| self.set.add(Node::Lam(b)))
+            },
+        }
+    }
+
+    /// Perform a single beta reduction step on the head of this expression, returning None
+    /// if this is already in weak head normal form (ie a lambda, or an application whose head
+    /// is not a lambda). Never reduces under a lambda or in argument position.
+    pub fn whnf_step(&mut self, analyzed_free_vars: &mut AnalyzedExpr<FreeVarAnalysis>) -> Option<Idx> {
+        assert_eq!(self.set.order, Order::ChildFirst);
+        if self.idx == HOLE {
+            return None
+        }
+        match self.node().clone() {
+            Node::App(f, x) => {
+                if let Some(b) = lam_body(self.set, f) {
+                    return Some(beta_redex(self.set, b, x, analyzed_free_vars));
+                }
+                self.get(f).whnf_step(analyzed_free_vars).map(|f| self.set.add(Node::App(f, x)))
+            },
+            _ => None,
+        }
+    }
+
+    /// Reduce to beta normal form taking at most `fuel` steps
+    pub fn beta_normal(&mut self, fuel: usize, analyzed_free_vars: &mut AnalyzedExpr<FreeVarAnalysis>) -> Reduced {
+        self.reduce_with(fuel, analyzed_free_vars, |e| e.is_beta_normal(), |e, fv| e.beta_step(fv))
+    }
+
+    /// Reduce to weak head normal form taking at most `fuel` steps
+    pub fn beta_whnf(&mut self, fuel: usize, analyzed_free_vars: &mut AnalyzedExpr<FreeVarAnalysis>) -> Reduced {
+        self.reduce_with(fuel, analyzed_free_vars, |e| e.is_whnf(), |e, fv| e.whnf_step(fv))
+    }
+
+    fn reduce_with(
+        &mut self,
+        fuel: usize,
+        analyzed_free_vars: &mut AnalyzedExpr<FreeVarAnalysis>,
+        is_normal: fn(Expr) -> bool,
+        step: fn(&mut ExprMut, &mut AnalyzedExpr<FreeVarAnalysis>) -> Option<Idx>,
+    ) -> Reduced {
+        let mut idx = self.idx;
+        let mut fuel = fuel;
+        loop {
+            // check before stepping so running out of fuel never costs an extra step
+            if is_normal(self.set.get(idx)) {
+                return Reduced::Normal(idx);
+            }
+            if fuel == 0 {
+                return Reduced::OutOfFuel(idx);
+            }
+            fuel -= 1;
+            idx = step(&mut self.get(idx), analyzed_free_vars).unwrap();
+        }
+    }
+}
+
+impl<'a> Expr<'a> {
+    /// true if there's no beta redex anywhere in this expression, ie beta_step() would return None
+    pub fn is_beta_normal(&self) -> bool {
+        if self.idx == HOLE {
+            return true
+        }
+        match self.node() {
+            Node::App(f, x) => lam_body(self.set, *f).is_none() && self.get(*f).is_beta_normal() && self.get(*x).is_beta_normal(),
+            Node::Lam(b) => self.get(*b).is_beta_normal(),
+            _ => true,
+        }
+    }
+
+    /// true if the head of this expression is not a beta redex, ie whnf_step() would return None
+    pub fn is_whnf(&self) -> bool {
+        let mut idx = self.idx;
+        while idx != HOLE {
+            let Node::App(f, _) = self.get_node(idx) else { break };
+            if lam_body(self.set, *f).is_some() {
+                return false;
+            }
+            idx = *f;
+        }
+        true
+    }
+}
+
+/// the body of `idx` if it's a lambda (and not a HOLE)
+fn lam_body(set: &ExprSet, idx: Idx) -> Option<Idx> {
+    if idx == HOLE {
+        return None
+    }
+    match set[idx] {
+        Node::Lam(b) => Some(b),
+        _ => None,
+    }
+}
+
+/// reduce the redex `(app (lam body) arg)`: upshift the arg so its free vars survive
+/// being placed under the lambda, substitute it for $0, then downshift the result
+/// to account for the removed lambda.
+fn beta_redex(set: &mut ExprSet, body: Idx, arg: Idx, analyzed_free_vars: &mut AnalyzedExpr<FreeVarAnalysis>) -> Idx {
+    let arg = set.get_mut(arg).shift(1, 0, analyzed_free_vars);
+    let substituted = set.get_mut(body).subst(0, arg, analyzed_free_vars);
+    set.get_mut(substituted).shift(-1, 0, analyzed_free_vars)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_normal(set: &mut ExprSet, in_s: &str, out_s: &str) {
+        let mut fv = AnalyzedExpr::new(FreeVarAnalysis);
+        let e = set.parse_extend(in_s).unwrap();
+        let res = set.get_mut(e).beta_normal(100, &mut fv);
+        assert!(res.is_normal());
+        assert_eq!(set.get(res.idx()).to_string(), out_s.to_string());
+    }
+
+    fn assert_whnf(set: &mut ExprSet, in_s: &str, out_s: &str) {
+        let mut fv = AnalyzedExpr::new(FreeVarAnalysis);
+        let e = set.parse_extend(in_s).unwrap();
+        let res = set.get_mut(e).beta_whnf(100, &mut fv);
+        assert!(res.is_normal());
+        assert_eq!(set.get(res.idx()).to_string(), out_s.to_string());
+    }
+
+    #[test]
+    fn test_beta() {
+        for struct_hash in [false, true] {
+            let set = &mut ExprSet::empty(Order::ChildFirst, true, struct_hash);
+            assert_normal(set, "(+ 1 2)", "(+ 1 2)");
+            assert_normal(set, "((lam $0) foo)", "foo");
+            assert_normal(set, "((lam (+ $0 $0)) 3)", "(+ 3 3)");
+            assert_normal(set, "((lam (lam $1)) a b)", "a");
+            assert_normal(set, "((lam (lam $0)) a b)", "b");
+            // free variables in the body get downshifted
+            assert_normal(set, "((lam (+ $0 $1)) 3)", "(+ 3 $0)");
+            // free variables in the arg get upshifted when going under a lambda
+            assert_normal(set, "((lam (lam ($1 $0))) $0)", "(lam ($1 $0))");
+            assert_normal(set, "(lam ((lam (lam $1)) $0))", "(lam (lam $1))");
+            // reduction under lambdas and in argument position
+            assert_normal(set, "(lam (foo ((lam $0) $0)))", "(lam (foo $0))");
+            assert_normal(set, "((lam ($0 $0)) (lam (bar $0)))", "(bar (lam (bar $0)))");
+
+            // whnf doesn't go under lambdas or into arguments
+            assert_whnf(set, "((lam (lam $1)) a)", "(lam a)");
+            assert_whnf(set, "(lam ((lam $0) a))", "(lam ((lam $0) a))");
+            assert_whnf(set, "(foo ((lam $0) a))", "(foo ((lam $0) a))");
+            assert_whnf(set, "((lam (lam $0)) a b)", "b");
+
+            // omega never terminates
+            let mut fv = AnalyzedExpr::new(FreeVarAnalysis);
+            let e = set.parse_extend("((lam ($0 $0)) (lam ($0 $0)))").unwrap();
+            let res = set.get_mut(e).beta_normal(10, &mut fv);
+            assert!(!res.is_normal());
+            assert_eq!(set.get(res.idx()).to_string(), "((lam ($0 $0)) (lam ($0 $0)))");
+
+            // running out of fuel doesn't take an extra step, and a normal form needs no fuel
+            let e = set.parse_extend("((lam $0) ((lam $0) a))").unwrap();
+            let len = set.len();
+            assert_eq!(set.get_mut(e).beta_normal(0, &mut fv), Reduced::OutOfFuel(e));
+            assert_eq!(set.len(), len);
+            assert!(!set.get_mut(e).beta_normal(1, &mut fv).is_normal());
+            assert!(set.get_mut(e).beta_normal(2, &mut fv).is_normal());
+            let e = set.parse_extend("(lam (foo $0))").unwrap();
+            assert_eq!(set.get_mut(e).beta_normal(0, &mut fv), Reduced::Normal(e));
+
+            // single steps
+            let e = set.parse_extend("((lam $0) ((lam $0) a))").unwrap();
+            let e = set.get_mut(e).beta_step(&mut fv).unwrap();
+            assert_eq!(set.get(e).to_string(), "((lam $0) a)");
+            let e = set.get_mut(e).beta_step(&mut fv).unwrap();
+            assert_eq!(set.get(e).to_string(), "a");
+            assert_eq!(set.get_mut(e).beta_step(&mut fv), None);
+        }
+    }
+}
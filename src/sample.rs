@@ -0,0 +1,194 @@
+use std::collections::VecDeque;
+
+use crate::*;
+
+/// Limits for Sampler::sample()
+#[derive(Debug, Clone)]
+pub struct SampleConfig {
+    pub max_depth: usize, // the deepest node allowed, where the root is at depth 0 and arguments and lambda bodies are one deeper
+    pub max_size: usize, // the most nodes (counting each App and Lam) a program may have
+    pub max_steps: usize, // give up on a program after trying this many choices, counting ones that were backtracked out of
+}
+
+/// Samples random well typed programs top down from a Grammar, so choices are weighted by the grammar's weights (use
+/// Grammar::uniform() for uniform choices). At each node a production, variable, or `lam` is picked among the ones that
+/// fit the needed type (see DSL::candidates()) and its arguments are sampled in turn, with type variables resolved by
+/// unification as they go. When a choice leads to a dead end (say it can't be finished within the limits) it's rolled
+/// back and another is picked. Sampling is deterministic given the seed.
+pub struct Sampler<'a, D: Domain> {
+    pub grammar: &'a Grammar<'a, D>,
+    pub config: SampleConfig,
+    rng: Rng,
+}
+
+impl<'a, D: Domain> Sampler<'a, D> {
+    pub fn new(grammar: &'a Grammar<'a, D>, seed: u64, config: SampleConfig) -> Self {
+        Sampler { grammar, config, rng: Rng(seed) }
+    }
+
+    /// sample a closed program of type `tp` (where type variables in `tp` may be bound to anything), or None if
+    /// no program was found within the limits
+    pub fn sample(&mut self, tp: &SlowType) -> Option<ExprOwned> {
+        let mut set = ExprSet::empty(Order::ChildFirst, false, false);
+        let mut ctx = Context::empty();
+        let tp = tp.instantiate(&mut ctx);
+        let (mut size, mut steps) = (0, 0);
+        let idx = self.sample_aux(&mut set, &tp, &mut VecDeque::new(), &mut ctx, 0, &mut size, &mut steps)?;
+        Some(ExprOwned::new(set, idx))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn sample_aux(&mut self, set: &mut ExprSet, tp: &SlowType, env: &mut VecDeque<SlowType>, ctx: &mut Context, depth: usize, size: &mut usize, steps: &mut usize) -> Option<Idx> {
+        let tp = tp.apply(ctx);
+        let candidates = self.grammar.dsl.candidates(&tp, env, ctx);
+
+        // weighted like Grammar::log_likelihood(), where a variable's share of the weight is split between the variables
+        let mut vars: Vec<&Node> = candidates.iter().map(|c| &c.node).filter(|node| matches!(node, Node::Var(_))).collect();
+        vars.dedup();
+        let num_vars = vars.len();
+        let mut choices: Vec<(Option<Candidate>, f32)> = vec![]; // None is `lam`
+        for candidate in candidates.iter() {
+            let num_args = candidate.arg_tps.len();
+            if (num_args > 0 && depth == self.config.max_depth) || *size + 1 + 2 * num_args > self.config.max_size {
+                continue
+            }
+            let log_weight = match &candidate.node {
+                Node::Prim(p) => self.grammar.log_prob(p),
+                _ => self.grammar.log_variable - (num_vars as f32).ln(),
+            };
+            choices.push((Some(candidate.clone()), log_weight));
+        }
+        if (tp.is_arrow() || matches!(tp, SlowType::Var(_))) && depth < self.config.max_depth && *size + 2 <= self.config.max_size {
+            choices.push((None, self.grammar.log_lam));
+        }
+        let max_log_weight = choices.iter().map(|(_, w)| *w).fold(f32::NEG_INFINITY, f32::max);
+        let mut choices: Vec<(Option<Candidate>, f64)> = choices.into_iter()
+            .map(|(c, w)| (c, ((w - max_log_weight) as f64).exp()))
+            .filter(|(_, w)| *w > 0.)
+            .collect();
+
+        // try choices at random without replacement until one works out
+        while !choices.is_empty() {
+            *steps += 1;
+            if *steps > self.config.max_steps {
+                return None
+            }
+            let total: f64 = choices.iter().map(|(_, w)| w).sum();
+            let mut r = self.rng.next_f64() * total;
+            let i = choices.iter().position(|(_, w)| { r -= w; r < 0. }).unwrap_or(choices.len() - 1);
+            let (choice, _) = choices.swap_remove(i);
+
+            let set_state = set.save_state();
+            let ctx_state = ctx.save_state();
+            let size_before = *size;
+            let res = match choice {
+                None => {
+                    let arg_tp = ctx.fresh_type_var();
+                    let body_tp = ctx.fresh_type_var();
+                    ctx.unify(&tp, &SlowType::arrow(arg_tp.clone(), body_tp.clone())).ok().and_then(|_| {
+                        *size += 1;
+                        env.push_front(arg_tp);
+                        let body = self.sample_aux(set, &body_tp, env, ctx, depth + 1, size, steps);
+                        env.pop_front();
+                        body.map(|b| set.add(Node::Lam(b)))
+                    })
+                },
+                Some(candidate) => {
+                    *ctx = candidate.ctx;
+                    *size += 1 + candidate.arg_tps.len();
+                    let mut idx = Some(set.add(candidate.node));
+                    for arg_tp in candidate.arg_tps.iter() {
+                        let Some(f) = idx else { break };
+                        idx = self.sample_aux(set, arg_tp, env, ctx, depth + 1, size, steps).map(|x| set.add(Node::App(f, x)));
+                    }
+                    idx
+                },
+            };
+            if res.is_some() {
+                return res
+            }
+            set.load_state(set_state);
+            ctx.load_state(ctx_state);
+            *size = size_before;
+        }
+        None
+    }
+}
+
+/// a small splitmix64 generator, so sampling is reproducible from a seed
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use domains::simple::SimpleVal;
+
+    #[test]
+    fn test_sample() {
+        let dsl = SimpleVal::new_dsl();
+        let grammar = Grammar::uniform(&dsl);
+        let config = SampleConfig { max_depth: 4, max_size: 15, max_steps: 1000 };
+        let samples = |grammar: &Grammar<SimpleVal>, tp: &SlowType, seed: u64| -> Vec<ExprOwned> {
+            let mut sampler = Sampler::new(grammar, seed, config.clone());
+            (0..50).filter_map(|_| sampler.sample(tp)).collect()
+        };
+        fn depth(e: Expr) -> usize {
+            e.children().map(|c| depth(e.get(c)) + if let Node::App(f, _) = e.node() { (c != *f) as usize } else { 1 }).max().unwrap_or(0)
+        }
+
+        for tp in ["int", "int -> int", "list int -> int", "(list t0) -> t0 -> int"] {
+            let tp: SlowType = tp.parse().unwrap();
+            let progs = samples(&grammar, &tp, 0);
+            assert!(progs.len() > 40, "only sampled {} programs of type {}", progs.len(), tp);
+            for e in progs.iter() {
+                let mut ctx = Context::empty();
+                let inferred = e.immut().infer(&mut ctx, &mut Default::default(), &dsl).unwrap();
+                let tp = tp.instantiate(&mut ctx);
+                assert!(ctx.unify(&inferred, &tp).is_ok(), "{} isn't a {}", e, tp);
+                assert!(e.immut().cost_rec(&ExprCost::num_nodes()) as usize <= config.max_size);
+                assert!(depth(e.immut()) <= config.max_depth, "{} is too deep", e);
+            }
+            // they vary
+            let mut strs: Vec<String> = progs.iter().map(|e| e.to_string()).collect();
+            strs.sort();
+            strs.dedup();
+            assert!(strs.len() > 10);
+        }
+
+        // deterministic given the seed
+        let tp: SlowType = "int".parse().unwrap();
+        let strs = |progs: Vec<ExprOwned>| progs.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        assert_eq!(strs(samples(&grammar, &tp, 3)), strs(samples(&grammar, &tp, 3)));
+        assert_ne!(strs(samples(&grammar, &tp, 3)), strs(samples(&grammar, &tp, 4)));
+
+        // weighted choices
+        let mut weighted = Grammar::uniform(&dsl);
+        weighted.log_probs.insert("*".into(), f32::NEG_INFINITY);
+        weighted.log_probs.insert("0".into(), (100f32).ln());
+        let progs = strs(samples(&weighted, &tp, 0));
+        assert!(progs.iter().all(|s| !s.contains('*')));
+        assert!(progs.iter().filter(|s| *s == "0").count() > 25);
+
+        // dead ends
+        let tiny = SampleConfig { max_depth: 0, ..config.clone() };
+        assert!(Sampler::new(&grammar, 0, tiny.clone()).sample(&"int -> int".parse().unwrap()).is_none());
+        assert_eq!(Sampler::new(&grammar, 0, tiny).sample(&"list int".parse().unwrap()).unwrap().to_string(), "[]");
+    }
+}
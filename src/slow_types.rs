@@ -39,14 +39,14 @@ impl SlowType {
         }
     }
 
-    /// iterates over all (left_type,right_type) pairs for the chain of arrows
-    /// starting here. Empty iterator if this is not an arrow.
+    // iterates over all (left_type,right_type) pairs for the chain of arrows
+    // starting here. Empty iterator if this is not an arrow.
     // pub fn iter_nodes(&self) -> impl Iterator<Item=&Type> {
     //     return NodeIter { curr: self }
     // }
 
     /// iterates over all nodes in the term of this type
-    pub fn iter_arrows(&self) -> ArrowIter {
+    pub fn iter_arrows(&self) -> ArrowIter<'_> {
         ArrowIter { curr: self }
     }
 
@@ -166,7 +166,7 @@ impl<'a> Iterator for ArrowIter<'a> {
 
 
 impl std::str::FromStr for SlowType {
-    type Err = String;
+    type Err = ParseError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         parse_type::parse(s)
     }
@@ -252,7 +252,7 @@ impl Context {
         self.next_var = state.1;
     }
 
-    fn fresh_type_var(&mut self) -> SlowType {
+    pub(crate) fn fresh_type_var(&mut self) -> SlowType {
         if !self.append_only {
             self.subst_unionfind.push(None);
         }
@@ -396,12 +396,20 @@ impl std::fmt::Display for Context {
 
 impl<'a> Expr<'a> {
     pub fn infer<D: Domain>(&self, ctx: &mut Context, env: &mut VecDeque<SlowType>, dsl: &DSL<D>) -> Result<SlowType,UnifyErr> {
+        self.infer_holes(ctx, env, dsl, &mut vec![])
+    }
+
+    /// infer() that also pushes the type and environment of each typed hole it encounters onto `holes`, once per
+    /// hole id. The types are relative to `ctx` and should be apply()ed once inference is done. Every occurrence of
+    /// a hole must have the same type (and the same types for the variables they share), and a hole with recorded
+    /// info in the set's hole table must fit that info once instantiated, otherwise this returns the unify error.
+    pub fn infer_holes<D: Domain>(&self, ctx: &mut Context, env: &mut VecDeque<SlowType>, dsl: &DSL<D>, holes: &mut Vec<(HoleId, HoleInfo)>) -> Result<SlowType,UnifyErr> {
         // println!("infer({})", self.to_string_uncurried(child));
         match self.node() {
             Node::App(f,x) => {
                 let return_tp = ctx.fresh_type_var();
-                let x_tp = self.get(*x).infer::<D>(ctx, env, dsl)?;
-                let f_tp = self.get(*f).infer::<D>(ctx, env, dsl)?;
+                let x_tp = self.get(*x).infer_holes::<D>(ctx, env, dsl, holes)?;
+                let f_tp = self.get(*f).infer_holes::<D>(ctx, env, dsl, holes)?;
                 ctx.unify(&f_tp, &SlowType::arrow(x_tp, return_tp.clone()))?;
                 Ok(return_tp.apply(ctx))
             },
@@ -409,10 +417,30 @@ impl<'a> Expr<'a> {
                 let var_tp = ctx.fresh_type_var();
                 // todo maybe optimize by making this a vecdeque for faster insert/remove at the zero index
                 env.push_front(var_tp.clone());
-                let body_tp = self.get(*b).infer::<D>(ctx, env, dsl)?;
+                let body_tp = self.get(*b).infer_holes::<D>(ctx, env, dsl, holes)?;
                 env.pop_front();
                 Ok(SlowType::arrow(var_tp, body_tp).apply(ctx))
             },
+            Node::Hole(id) => {
+                // an earlier occurrence or the recorded info, which every occurrence must agree with
+                let expected = match holes.iter().find(|(i,_)| i == id) {
+                    Some((_, info)) => Some(info.clone()),
+                    None => self.set.hole_info(*id).map(|info| info.instantiate(ctx)),
+                };
+                let tp = match expected {
+                    Some(expected) => {
+                        for (tp, expected_tp) in env.iter().zip(expected.env.iter()) {
+                            ctx.unify(tp, expected_tp)?;
+                        }
+                        expected.tp
+                    },
+                    None => ctx.fresh_type_var(),
+                };
+                if !holes.iter().any(|(i,_)| i == id) {
+                    holes.push((*id, HoleInfo::new(tp.clone(), env.clone())));
+                }
+                Ok(tp.apply(ctx))
+            },
             Node::Var(i) => {
                 if (*i as usize) >= env.len() {
                     panic!("unbound variable encountered during infer(): ${}", i)
@@ -32,5 +32,5 @@ where T: FromVal<D> + Debug + PartialEq
     let args = Env::from(args);
     let res = set.get(e).eval(&args, &D::new_dsl(), None);
     assert!(res.is_err());
-    assert_eq!(expected_error_msg, res.err().unwrap());
+    assert_eq!(expected_error_msg, res.err().unwrap().to_string());
 }
\ No newline at end of file